@@ -1,12 +1,56 @@
 search_entries = Search
 delete_entry = Delete
 incognito = Incognito
+pause_5_minutes = Pause 5 min
+pause_1_hour = Pause 1 hour
+pause_until_logout = Pause until logout
+paused_for = Paused, { $remaining } left
+paused_until_logout = Paused until logout
+resume = Resume
 clear_entries = Clear
+clear_last_15_minutes = Clear last 15 minutes
+clear_last_hour = Clear last hour
+clear_last_day = Clear last day
+clear_older_than_week = Clear older than a week
+entry_deleted = Entry deleted
+entries_cleared = History cleared
+undo = Undo
+entries_deleted = { $count ->
+    [one] Entry deleted
+   *[other] { $count } entries deleted
+}
+selected_entries = { $count ->
+    [one] 1 entry selected
+   *[other] { $count } entries selected
+}
+cancel = Cancel
+copy_concatenated = Copy together
+export = Export
+concatenation_separator = Separator when copied together
 show_qr_code = Show QR code
 return_to_clipboard = Return to clipboard
 qr_code_error = Error while generating the QR code
+retry = Retry
 horizontal_layout = Horizontal
 add_favorite = Add Favorite
 remove_favorite = Remove Favorite
 unique_session = Unique session
+fuzzy_search = Fuzzy search
+sort_by_usage = Most used first
+keep_copied_position = Keep the position of copied entries
+text_recognition = Recognize text in images
+copy_recognized_text = Copy recognized text
+copy_to_primary = Copy to primary selection
+keep_clipboard_alive = Keep clipboard content
+new_entries_badge = Count new entries on the panel icon
+notify_errors = Notify when the clipboard can't be watched
+notify_filtered = Notify when a copy is not recorded
+notify_copied = Notify when an entry is copied
+clipboard_error = The clipboard history is not recorded
+offer_filtered = Copy not recorded
+offer_filtered_body = None of its types is allowed by the filters, or within the size and time limits
+copied = Copied
+duplicates_ignore_whitespace = Merge texts differing by whitespace
+duplicates_ignore_case = Merge texts differing by case
+merge_duplicates = Merge duplicates
 unknown_mime_types_title = Mime types
\ No newline at end of file
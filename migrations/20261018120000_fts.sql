@@ -0,0 +1,26 @@
+-- Full-text index of the text contents, used to prefilter the search.
+-- The trigram tokenizer allows substring matching.
+CREATE VIRTUAL TABLE IF NOT EXISTS ClipboardContentsFts USING fts5 (
+    content,
+    content = 'ClipboardContents',
+    tokenize = 'trigram remove_diacritics 1'
+);
+
+INSERT INTO ClipboardContentsFts (rowid, content)
+SELECT rowid, CAST(content AS TEXT)
+FROM ClipboardContents
+WHERE mime LIKE 'text/%';
+
+CREATE TRIGGER IF NOT EXISTS ClipboardContentsFtsInsert AFTER INSERT ON ClipboardContents
+WHEN new.mime LIKE 'text/%'
+BEGIN
+    INSERT INTO ClipboardContentsFts (rowid, content)
+    VALUES (new.rowid, CAST(new.content AS TEXT));
+END;
+
+CREATE TRIGGER IF NOT EXISTS ClipboardContentsFtsDelete AFTER DELETE ON ClipboardContents
+WHEN old.mime LIKE 'text/%'
+BEGIN
+    INSERT INTO ClipboardContentsFts (ClipboardContentsFts, rowid, content)
+    VALUES ('delete', old.rowid, CAST(old.content AS TEXT));
+END;
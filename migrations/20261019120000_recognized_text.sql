@@ -0,0 +1,6 @@
+-- Text extracted from image entries, searchable like text contents.
+CREATE TABLE IF NOT EXISTS ClipboardRecognizedTexts (
+    id INTEGER PRIMARY KEY,
+    text TEXT NOT NULL,
+	FOREIGN KEY (id) REFERENCES ClipboardEntries(id) ON DELETE CASCADE
+);
@@ -0,0 +1,76 @@
+-- Contents stored once, whatever the number of mime types and entries using them.
+-- The hash of the migrated contents is computed by the application, on startup.
+CREATE TABLE IF NOT EXISTS ClipboardBlobs (
+    id INTEGER PRIMARY KEY,
+    hash BLOB UNIQUE,
+    content BLOB NOT NULL
+);
+
+INSERT INTO ClipboardBlobs (id, content)
+SELECT MIN(rowid), content
+FROM ClipboardContents
+GROUP BY content;
+
+CREATE TABLE ClipboardContentsBlobs (
+    id INTEGER NOT NULL,
+    mime TEXT NOT NULL,
+    blob INTEGER NOT NULL,
+    PRIMARY KEY (id, mime),
+	FOREIGN KEY (id) REFERENCES ClipboardEntries(id) ON DELETE CASCADE,
+	FOREIGN KEY (blob) REFERENCES ClipboardBlobs(id)
+);
+
+INSERT INTO ClipboardContentsBlobs (id, mime, blob)
+SELECT id, mime, MIN(rowid) OVER (PARTITION BY content)
+FROM ClipboardContents;
+
+DROP TRIGGER ClipboardContentsFtsInsert;
+DROP TRIGGER ClipboardContentsFtsDelete;
+DROP TABLE ClipboardContentsFts;
+DROP TABLE ClipboardContents;
+
+ALTER TABLE ClipboardContentsBlobs RENAME TO ClipboardContents;
+
+CREATE INDEX IF NOT EXISTS index_blob ON ClipboardContents (blob);
+
+-- Indexed by blob, so a text offered with several mime types is indexed once.
+CREATE VIRTUAL TABLE IF NOT EXISTS ClipboardContentsFts USING fts5 (
+    content,
+    content = '',
+    contentless_delete = 1,
+    tokenize = 'trigram remove_diacritics 1'
+);
+
+INSERT INTO ClipboardContentsFts (rowid, content)
+SELECT id, CAST(content AS TEXT)
+FROM ClipboardBlobs
+WHERE id IN (
+    SELECT blob
+    FROM ClipboardContents
+    WHERE mime LIKE 'text/%'
+);
+
+CREATE TRIGGER IF NOT EXISTS ClipboardContentsFtsInsert AFTER INSERT ON ClipboardContents
+WHEN new.mime LIKE 'text/%' AND NOT EXISTS (
+    SELECT 1
+    FROM ClipboardContents
+    WHERE blob = new.blob AND mime LIKE 'text/%' AND rowid != new.rowid
+)
+BEGIN
+    INSERT INTO ClipboardContentsFts (rowid, content)
+    SELECT id, CAST(content AS TEXT)
+    FROM ClipboardBlobs
+    WHERE id = new.blob;
+END;
+
+-- A blob is deleted with the last content using it.
+CREATE TRIGGER IF NOT EXISTS ClipboardBlobsRelease AFTER DELETE ON ClipboardContents
+WHEN NOT EXISTS (
+    SELECT 1
+    FROM ClipboardContents
+    WHERE blob = old.blob
+)
+BEGIN
+    DELETE FROM ClipboardBlobs WHERE id = old.blob;
+    DELETE FROM ClipboardContentsFts WHERE rowid = old.blob;
+END;
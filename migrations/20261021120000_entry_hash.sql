@@ -0,0 +1,5 @@
+-- Hash of the content of the entries, to deduplicate them.
+-- The hash of the existing entries is computed by the application.
+ALTER TABLE ClipboardEntries ADD COLUMN hash BLOB;
+
+CREATE INDEX IF NOT EXISTS index_hash ON ClipboardEntries (hash);
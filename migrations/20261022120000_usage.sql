@@ -0,0 +1,4 @@
+-- Copies of the entries from the history.
+ALTER TABLE ClipboardEntries ADD COLUMN copy_count INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE ClipboardEntries ADD COLUMN last_used INTEGER;
@@ -0,0 +1,4 @@
+-- Time the entry was moved to the trash. It can be restored until purged.
+ALTER TABLE ClipboardEntries ADD COLUMN deleted INTEGER;
+
+CREATE INDEX IF NOT EXISTS index_deleted ON ClipboardEntries (deleted);
@@ -0,0 +1,10 @@
+-- Entries moved to the trash together are restored together, even when
+-- trashed during the same millisecond as another deletion.
+CREATE TABLE IF NOT EXISTS TrashBatches (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time INTEGER NOT NULL
+);
+
+ALTER TABLE ClipboardEntries ADD COLUMN trash_batch INTEGER;
+
+CREATE INDEX IF NOT EXISTS index_trash_batch ON ClipboardEntries (trash_batch);
@@ -0,0 +1,25 @@
+-- Full-text index of the recognized texts, so the search prefilter doesn't keep
+-- every image entry with a text.
+CREATE VIRTUAL TABLE IF NOT EXISTS ClipboardRecognizedTextsFts USING fts5 (
+    text,
+    content = '',
+    contentless_delete = 1,
+    tokenize = 'trigram remove_diacritics 1'
+);
+
+INSERT INTO ClipboardRecognizedTextsFts (rowid, text)
+SELECT id, text
+FROM ClipboardRecognizedTexts;
+
+-- Also on a replacement, which doesn't fire the delete trigger.
+CREATE TRIGGER IF NOT EXISTS ClipboardRecognizedTextsFtsInsert AFTER INSERT ON ClipboardRecognizedTexts
+BEGIN
+    DELETE FROM ClipboardRecognizedTextsFts WHERE rowid = new.id;
+    INSERT INTO ClipboardRecognizedTextsFts (rowid, text)
+    VALUES (new.id, new.text);
+END;
+
+CREATE TRIGGER IF NOT EXISTS ClipboardRecognizedTextsFtsDelete AFTER DELETE ON ClipboardRecognizedTexts
+BEGIN
+    DELETE FROM ClipboardRecognizedTextsFts WHERE rowid = old.id;
+END;
@@ -0,0 +1,3 @@
+-- The hash now covers every content of the entries, not only the preferred text.
+-- It is computed again by the application.
+UPDATE ClipboardEntries SET hash = NULL;
@@ -2,6 +2,7 @@ use chrono::Utc;
 use cosmic::app::Core;
 
 use cosmic::iced::clipboard::mime::AsMimeTypes;
+use cosmic::iced::keyboard::Modifiers;
 use cosmic::iced::keyboard::key::Named;
 use cosmic::iced::window::Id;
 use cosmic::iced::{self, Limits};
@@ -16,23 +17,26 @@ use cosmic::iced_winit::commands::layer_surface::{
     self, KeyboardInteractivity, destroy_layer_surface, get_layer_surface,
 };
 use cosmic::iced_winit::commands::popup::{destroy_popup, get_popup};
-use cosmic::widget::{MouseArea, Space};
+use cosmic::widget::toaster::{Toast, Toasts};
+use cosmic::widget::{self, MouseArea, Space, row};
 
 use cosmic::{Element, app::Task};
 use futures::StreamExt;
 use futures::executor::block_on;
 use regex::Regex;
 
-use crate::clipboard::ClipboardError;
+use crate::clipboard::{ClipboardHandler, ClipboardState, Handled, READ_POLICY, ReadPolicy};
 use crate::config::{Config, PRIVATE_MODE};
-use crate::db::{DbMessage, DbTrait, EntryTrait, MimeDataMap};
-use crate::message::{AppMsg, ConfigMsg, ContextMenuMsg};
+use crate::db::{Content, DbMessage, DbTrait, EntryId, EntryTrait, MimeDataMap};
+use crate::message::{AppMsg, ConfigMsg, ContextMenuMsg, SelectionMsg};
 use crate::navigation::EventMsg;
-use crate::utils::task_message;
+use crate::selection::Selection;
+use crate::utils::{self, task_message};
 use crate::view::SCROLLABLE_ID;
-use crate::{clipboard, clipboard_watcher, config, navigation};
+use crate::{clipboard, config, db, export, fl, navigation, notification, ocr};
 
 use cosmic::{cosmic_config, iced_runtime};
+use std::collections::VecDeque;
 use std::sync::atomic::{self};
 use std::time::Duration;
 
@@ -47,33 +51,26 @@ pub struct AppState<Db: DbTrait> {
     popup: Option<Popup>,
     pub config: Config,
     pub db: Db,
-    pub clipboard_state: ClipboardState,
+    pub clipboard: ClipboardHandler,
+    /// Incremented to restart the clipboard subscription
+    clipboard_retry: u32,
+    /// Image entries waiting for their text recognition
+    text_recognition_queue: VecDeque<EntryId>,
+    /// To undo the deletions
+    pub toasts: Toasts<AppMsg>,
+    /// Recording paused without changing the config
+    pub pause: Option<Pause>,
+    /// Entries captured since the popup was last opened
+    new_entries: usize,
     pub focused: usize,
+    pub selected: Selection,
+    modifiers: Modifiers,
     pub page: usize,
     pub qr_code: Option<Result<qr_code::Data, ()>>,
     last_quit: Option<(i64, PopupKind)>,
     pub preferred_mime_types_regex: Vec<Regex>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ClipboardState {
-    Init,
-    Connected,
-    Error(ErrorState),
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ErrorState {
-    MissingDataControlProtocol,
-    Other(String),
-}
-
-impl ClipboardState {
-    pub fn is_error(&self) -> bool {
-        matches!(self, ClipboardState::Error(..))
-    }
-}
-
 #[derive(Clone, Debug)]
 pub struct Flags {
     pub config_handler: cosmic_config::Config,
@@ -86,6 +83,14 @@ struct Popup {
     pub id: window::Id,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Pause {
+    /// Time in ms
+    Until(i64),
+    /// The applet exits with the session
+    UntilLogout,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum PopupKind {
     Popup,
@@ -157,6 +162,294 @@ impl<Db: DbTrait> AppState<Db> {
         }
     }
 
+    /// Search the current query in the background.
+    fn search(&mut self) -> Task<AppMsg> {
+        match self.db.start_search() {
+            Some(stream) => Task::run(stream, |results| {
+                cosmic::action::app(AppMsg::SearchResults(results))
+            }),
+            None => Task::none(),
+        }
+    }
+
+    /// The new copies are not recorded.
+    fn is_private(&self) -> bool {
+        self.config.private_mode || self.pause.is_some()
+    }
+
+    fn update_private_mode(&self) {
+        PRIVATE_MODE.store(self.is_private(), atomic::Ordering::Relaxed);
+    }
+
+    /// Name of the panel icon, reflecting the state of the recording.
+    fn panel_icon(&self) -> &'static str {
+        match self.clipboard.state {
+            ClipboardState::Error(_) => "dialog-warning-symbolic",
+            ClipboardState::Init => "content-loading-symbolic",
+            ClipboardState::Connected if self.is_private() => "view-conceal-symbolic",
+            ClipboardState::Connected => constcat::concat!(APPID, "-symbolic"),
+        }
+    }
+
+    /// Text next to the panel icon.
+    fn panel_label(&self) -> Option<String> {
+        if let Some(remaining) = self.pause_remaining() {
+            Some(utils::format_remaining(remaining))
+        } else if self.config.new_entries_badge && self.new_entries > 0 {
+            Some(self.new_entries.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Of a timed pause.
+    pub fn pause_remaining(&self) -> Option<Duration> {
+        match self.pause? {
+            Pause::Until(time) => {
+                let remaining = time - Utc::now().timestamp_millis();
+                Some(Duration::from_millis(remaining.max(0) as u64))
+            }
+            Pause::UntilLogout => None,
+        }
+    }
+
+    fn position(&self, id: EntryId) -> Option<usize> {
+        self.db.either_iter().position(|entry| entry.id() == id)
+    }
+
+    /// Select the entries between the anchor and `pos`. Without anchor,
+    /// the selection starts at `from`.
+    fn select_range(&mut self, from: usize, pos: usize) {
+        self.selected
+            .extend(self.db.either_iter().map(|entry| entry.id()), from, pos);
+    }
+
+    fn clear_selection(&mut self) {
+        self.selected.clear();
+    }
+
+    /// In the order they were copied.
+    fn selected_entries(&self) -> Vec<&Db::Entry> {
+        let mut entries = self
+            .selected
+            .ids()
+            .filter_map(|id| self.db.get_from_id(id))
+            .collect::<Vec<_>>();
+
+        entries.sort_by_key(|entry| entry.creation());
+        entries
+    }
+
+    fn update_selection(&mut self, message: SelectionMsg) -> Task<AppMsg> {
+        match message {
+            SelectionMsg::Toggle(id) => {
+                self.selected.toggle(id, self.position(id));
+            }
+            SelectionMsg::ExtendTo(id) => {
+                if let Some(pos) = self.position(id) {
+                    self.select_range(self.focused, pos);
+                }
+            }
+            SelectionMsg::Clear => self.clear_selection(),
+            SelectionMsg::Delete => {
+                let ids = self.selected.ids().collect::<Vec<_>>();
+                self.clear_selection();
+
+                if let Err(e) = block_on(self.db.delete_many(&ids)) {
+                    error!("can't delete the selected entries: {e}");
+                    return self.search();
+                }
+                return Task::batch([
+                    self.search(),
+                    self.undo_toast(fl!("entries_deleted", count = ids.len())),
+                ]);
+            }
+            SelectionMsg::AddFavorite => {
+                let ids = self
+                    .selected_entries()
+                    .into_iter()
+                    .filter(|entry| !entry.is_favorite())
+                    .map(|entry| entry.id())
+                    .collect::<Vec<_>>();
+
+                for id in ids {
+                    if let Err(e) = block_on(self.db.add_favorite(id, None)) {
+                        error!("can't add favorite: {e}");
+                    }
+                }
+                return self.search();
+            }
+            SelectionMsg::RemoveFavorite => {
+                let ids = self
+                    .selected_entries()
+                    .into_iter()
+                    .filter(|entry| entry.is_favorite())
+                    .map(|entry| entry.id())
+                    .collect::<Vec<_>>();
+
+                for id in ids {
+                    if let Err(e) = block_on(self.db.remove_favorite(id)) {
+                        error!("can't remove favorite: {e}");
+                    }
+                }
+                return self.search();
+            }
+            SelectionMsg::Export => {
+                let Some(dirs) = directories::UserDirs::new() else {
+                    error!("no home directory");
+                    return Task::none();
+                };
+                let parent = dirs.download_dir().unwrap_or(dirs.home_dir());
+
+                match export::export(
+                    self.selected_entries(),
+                    &self.preferred_mime_types_regex,
+                    parent,
+                ) {
+                    Ok(dir) => {
+                        info!("exported to {}", dir.display());
+                        if let Err(e) = open::that(&dir) {
+                            error!("{e}");
+                        }
+                    }
+                    Err(e) => error!("can't export the selected entries: {e}"),
+                }
+            }
+            SelectionMsg::CopyConcatenated => {
+                let separator = self.config.concatenation_separator();
+
+                let Some(data) = db::concatenate(self.selected_entries(), &separator) else {
+                    return Task::none();
+                };
+
+                self.clear_selection();
+
+                let task = self.copy(data.clone(), false);
+
+                // our own offer is not captured
+                return match block_on(self.clipboard.keep_alive.capture(
+                    &mut self.db,
+                    data,
+                    utils::now_millis(),
+                )) {
+                    Ok(_) => Task::batch([task, self.close_popup(), self.search()]),
+                    Err(e) => {
+                        error!("can't insert data: {e}");
+                        task
+                    }
+                };
+            }
+        }
+        Task::none()
+    }
+
+    /// Offer to undo the last deletion.
+    fn undo_toast(&mut self, message: String) -> Task<AppMsg> {
+        self.toasts
+            .push(Toast::new(message).action(fl!("undo"), AppMsg::UndoDelete))
+            .map(cosmic::action::app)
+    }
+
+    /// Extract the text of an image entry in the background, if enabled.
+    fn recognize_text(&self, id: EntryId) -> Task<AppMsg> {
+        match self.text_recognition(id) {
+            Some(recognition) => Task::future(recognition)
+                .and_then(move |text| task_message(AppMsg::TextRecognized(id, text))),
+            None => Task::none(),
+        }
+    }
+
+    /// Queue the image entries captured while the text recognition was disabled.
+    fn queue_text_recognition(&mut self) -> Task<AppMsg> {
+        self.text_recognition_queue = self
+            .db
+            .iter()
+            .filter(|entry| {
+                entry.recognized_text().is_none()
+                    && matches!(entry.preferred_content(&[]), Some((_, Content::Image(_))))
+            })
+            .map(|entry| entry.id())
+            .collect();
+
+        info!(
+            "recognize the text of {} images",
+            self.text_recognition_queue.len()
+        );
+
+        self.recognize_next_text()
+    }
+
+    /// Recognize the text of the next queued entry. One at a time, to not start
+    /// a process for each image of the history.
+    fn recognize_next_text(&mut self) -> Task<AppMsg> {
+        while let Some(id) = self.text_recognition_queue.pop_front() {
+            if let Some(recognition) = self.text_recognition(id) {
+                return Task::future(async move {
+                    cosmic::action::app(AppMsg::QueuedTextRecognized(id, recognition.await))
+                });
+            }
+        }
+
+        Task::none()
+    }
+
+    /// Extract the text of an image entry, if enabled and not done yet.
+    fn text_recognition(
+        &self,
+        id: EntryId,
+    ) -> Option<impl Future<Output = Option<String>> + Send + 'static> {
+        if !self.config.text_recognition {
+            return None;
+        }
+
+        let entry = self.db.get_from_id(id)?;
+
+        // already done before this entry was bumped
+        if entry.recognized_text().is_some() {
+            return None;
+        }
+
+        let Some((_, Content::Image(image))) = entry.preferred_content(&[]) else {
+            return None;
+        };
+        let image = image.to_vec();
+
+        Some(async move {
+            match tokio::task::spawn_blocking(move || ocr::recognize_text(&image)).await {
+                Ok(Ok(text)) => Some(text),
+                Ok(Err(e)) => {
+                    error!("can't recognize text: {e}");
+                    None
+                }
+                Err(e) => {
+                    error!("{e}");
+                    None
+                }
+            }
+        })
+    }
+
+    fn copy(&self, data: MimeDataMap, primary: bool) -> Task<AppMsg> {
+        if let Some(selection) = &self.clipboard.selection {
+            match selection.set_selection(data.clone(), primary) {
+                Ok(()) => return Task::none(),
+                Err(e) => error!("can't set the selection: {e}"),
+            }
+        }
+
+        if primary {
+            // iced only sets it from a focused surface
+            return Task::none();
+        }
+
+        copy_iced(data)
+    }
+
+    /// The primary selection can only be set by the clipboard backend.
+    pub fn can_set_primary(&self) -> bool {
+        self.clipboard.selection.is_some()
+    }
+
     fn toggle_popup(&mut self, kind: PopupKind) -> Task<AppMsg> {
         self.qr_code.take();
         match &self.popup {
@@ -174,7 +467,10 @@ impl<Db: DbTrait> AppState<Db> {
     fn close_popup(&mut self) -> Task<AppMsg> {
         self.focused = 0;
         self.page = 0;
-        self.db.set_query_and_search("".into());
+        self.clear_selection();
+        self.db.set_query(String::new());
+        // cancel the running search, if any
+        let _ = self.db.start_search();
 
         if let Some(popup) = self.popup.take() {
             // info!("destroy {:?}", popup.id);
@@ -207,6 +503,10 @@ impl<Db: DbTrait> AppState<Db> {
         let popup = Popup { kind, id: new_id };
         self.popup.replace(popup);
 
+        if kind == PopupKind::Popup {
+            self.new_entries = 0;
+        }
+
         match kind {
             PopupKind::Popup => {
                 if self.config.horizontal {
@@ -276,6 +576,7 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
     fn init(core: Core, flags: Self::Flags) -> (Self, Task<Self::Message>) {
         let config = flags.config;
         PRIVATE_MODE.store(config.private_mode, atomic::Ordering::Relaxed);
+        *READ_POLICY.write().unwrap() = ReadPolicy::new(&config);
 
         let db = block_on(async { Db::new(&config).await.unwrap() });
 
@@ -284,8 +585,15 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
             config_handler: flags.config_handler,
             popup: None,
             db,
-            clipboard_state: ClipboardState::Init,
+            clipboard: ClipboardHandler::default(),
+            clipboard_retry: 0,
+            text_recognition_queue: VecDeque::new(),
+            toasts: Toasts::new(AppMsg::CloseToast),
+            pause: None,
+            new_entries: 0,
             focused: 0,
+            selected: Selection::default(),
+            modifiers: Modifiers::empty(),
             qr_code: None,
             last_quit: None,
             page: 0,
@@ -337,8 +645,11 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
 
         match message {
             AppMsg::ChangeConfig(config) => {
-                if config.private_mode != self.config.private_mode {
-                    PRIVATE_MODE.store(config.private_mode, atomic::Ordering::Relaxed);
+                if config.maximum_mime_type_size != self.config.maximum_mime_type_size
+                    || config.allowed_mime_types != self.config.allowed_mime_types
+                    || config.ignored_mime_types != self.config.ignored_mime_types
+                {
+                    *READ_POLICY.write().unwrap() = ReadPolicy::new(&config);
                 }
                 if config.preferred_mime_types != self.config.preferred_mime_types {
                     self.preferred_mime_types_regex = config
@@ -353,7 +664,30 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
                         })
                         .collect();
                 }
+                let search_mode_changed = config.fuzzy_search != self.config.fuzzy_search;
+                let sort_order_changed = config.sort_by_usage != self.config.sort_by_usage;
+                let text_recognition_enabled =
+                    config.text_recognition && !self.config.text_recognition;
+                self.db.set_normalization(config.normalization());
                 self.config = config;
+                self.update_private_mode();
+
+                let recognize_texts = if text_recognition_enabled {
+                    self.queue_text_recognition()
+                } else {
+                    Task::none()
+                };
+
+                if sort_order_changed {
+                    self.db.set_sort_order(self.config.sort_order());
+                }
+                if search_mode_changed {
+                    self.db.set_search_mode(self.config.search_mode());
+                }
+                if search_mode_changed || sort_order_changed {
+                    return Task::batch([recognize_texts, self.search()]);
+                }
+                return recognize_texts;
             }
             AppMsg::ToggleQuickSettings => {
                 return self.toggle_popup(PopupKind::QuickSettings);
@@ -363,64 +697,203 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
             }
             AppMsg::ClosePopup => return self.close_popup(),
             AppMsg::Search(query) => {
-                self.db.set_query_and_search(query);
+                self.db.set_query(query);
+                return self.search();
+            }
+            AppMsg::SearchResults(results) => {
+                self.db.apply_search_results(results);
             }
-            AppMsg::ClipboardEvent(message) => match message {
-                clipboard::ClipboardMessage::Connected => {
-                    self.clipboard_state = ClipboardState::Connected;
+            AppMsg::TextRecognized(id, text) => {
+                if let Err(e) = block_on(self.db.set_recognized_text(id, text)) {
+                    error!("can't save recognized text: {e}");
                 }
-                clipboard::ClipboardMessage::Data(data) => {
-                    if let Err(e) = block_on(self.db.insert(data)) {
-                        error!("can't insert data: {e}");
-                    }
+                return self.search();
+            }
+            AppMsg::QueuedTextRecognized(id, text) => {
+                if let Some(text) = text
+                    && let Err(e) = block_on(self.db.set_recognized_text(id, text))
+                {
+                    error!("can't save recognized text: {e}");
                 }
-                #[expect(irrefutable_let_patterns)]
-                clipboard::ClipboardMessage::Error(e) => {
-                    error!("clipboard: {e}");
+                return Task::batch([self.recognize_next_text(), self.search()]);
+            }
+            AppMsg::ClipboardEvent(message) => {
+                let handled = block_on(self.clipboard.handle(
+                    &mut self.db,
+                    message,
+                    utils::now_millis(),
+                    self.config.keep_clipboard_alive,
+                ));
+
+                match handled {
+                    Ok(Handled::Nothing) => {}
+                    Ok(Handled::Captured(id)) => {
+                        let recognize_text = match id {
+                            Some(id) => {
+                                if self.popup.is_none() {
+                                    self.new_entries += 1;
+                                }
+                                self.recognize_text(id)
+                            }
+                            None => Task::none(),
+                        };
 
-                    self.clipboard_state = if let ClipboardError::Watch(ref e) = e
-                        && let clipboard_watcher::Error::MissingProtocol { name, .. } = **e
-                        && name == "zwlr_data_control_manager_v1"
-                    {
-                        ClipboardState::Error(ErrorState::MissingDataControlProtocol)
-                    } else {
-                        ClipboardState::Error(ErrorState::Other(e.to_string()))
-                    };
-                }
-                clipboard::ClipboardMessage::EmptyKeyboard => {
-                    if let Some(data) = self.db.get(0) {
-                        return copy_iced(data.raw_content().clone());
+                        return Task::batch([recognize_text, self.search()]);
+                    }
+                    Ok(Handled::Restore(data)) => return self.copy(data, false),
+                    Ok(Handled::Failed(e)) => {
+                        if self.config.notify_errors {
+                            return notify(fl!("clipboard_error"), e.to_string());
+                        }
+                    }
+                    Ok(Handled::Filtered) => {
+                        if self.config.notify_filtered {
+                            return notify(fl!("offer_filtered"), fl!("offer_filtered_body"));
+                        }
+                    }
+                    Err(e) => {
+                        error!("can't insert data: {e}");
                     }
                 }
-            },
+            }
             AppMsg::Copy(id) => {
                 let task = match self.db.get_from_id(id) {
-                    Some(data) => copy_iced(data.raw_content().clone()),
+                    Some(data) => {
+                        let notification = if self.config.notify_copied {
+                            notify(fl!("copied"), preview(data))
+                        } else {
+                            Task::none()
+                        };
+
+                        Task::batch([self.copy(data.raw_content().clone(), false), notification])
+                    }
                     None => {
                         error!("id not found");
                         Task::none()
                     }
                 };
 
-                return Task::batch([task, self.close_popup()]);
+                if let Err(e) = block_on(self.db.mark_used(id, self.config.copy_policy())) {
+                    error!("can't count the copy: {e}");
+                }
+
+                self.clipboard.keep_alive.captured(id);
+
+                return Task::batch([task, self.close_popup(), self.search()]);
             }
 
+            AppMsg::EntryPressed(id) => {
+                let message = if self.modifiers.control() {
+                    AppMsg::Selection(SelectionMsg::Toggle(id))
+                } else if self.modifiers.shift() {
+                    AppMsg::Selection(SelectionMsg::ExtendTo(id))
+                } else {
+                    AppMsg::Copy(id)
+                };
+
+                return task_message(message);
+            }
+            AppMsg::Selection(message) => {
+                return self.update_selection(message);
+            }
             AppMsg::CopySpecial(data) => {
-                return copy_iced(data);
+                return self.copy(data, false);
             }
             AppMsg::Clear => {
-                if let Err(e) = block_on(self.db.clear()) {
+                if let Err(e) = block_on(self.db.clear(true)) {
                     error!("can't clear db: {e}");
+                    return self.search();
+                }
+                return Task::batch([self.search(), self.undo_toast(fl!("entries_cleared"))]);
+            }
+            AppMsg::ClearRange(range) => {
+                let (from, to) = range.bounds(Utc::now().timestamp_millis());
+
+                match block_on(self.db.delete_range(from, to, true)) {
+                    Ok(0) => {}
+                    Ok(count) => {
+                        return Task::batch([
+                            self.search(),
+                            self.undo_toast(fl!("entries_deleted", count = count)),
+                        ]);
+                    }
+                    Err(e) => error!("can't clear the entries: {e}"),
+                }
+                return self.search();
+            }
+            AppMsg::UndoDelete(toast) => {
+                self.toasts.remove(toast);
+                match block_on(self.db.undo_delete()) {
+                    Ok(restored) => info!("restored {restored} entries"),
+                    Err(e) => error!("can't undo the deletion: {e}"),
+                }
+                return self.search();
+            }
+            AppMsg::CloseToast(toast) => {
+                self.toasts.remove(toast);
+            }
+            AppMsg::MergeDuplicates => {
+                match block_on(self.db.merge_duplicates()) {
+                    Ok(0) => {}
+                    Ok(merged) => {
+                        info!("merged {merged} duplicates");
+                        return Task::batch([
+                            self.search(),
+                            self.undo_toast(fl!("entries_deleted", count = merged)),
+                        ]);
+                    }
+                    Err(e) => error!("can't merge duplicates: {e}"),
+                }
+                return self.search();
+            }
+            AppMsg::Pause(duration) => {
+                self.pause = Some(match duration {
+                    Some(duration) => {
+                        Pause::Until(Utc::now().timestamp_millis() + duration.as_millis() as i64)
+                    }
+                    None => Pause::UntilLogout,
+                });
+                self.update_private_mode();
+            }
+            AppMsg::Resume => {
+                self.pause = None;
+                self.update_private_mode();
+            }
+            AppMsg::PauseTick => {
+                if self
+                    .pause_remaining()
+                    .is_some_and(|remaining| remaining.is_zero())
+                {
+                    info!("end of the pause");
+                    self.pause = None;
+                    self.update_private_mode();
                 }
             }
             AppMsg::RetryConnectingClipboard => {
-                self.clipboard_state = ClipboardState::Init;
+                self.clipboard.state = ClipboardState::Init;
+                self.clipboard_retry += 1;
             }
             AppMsg::Navigation(message) => match message {
                 EventMsg::Event(e) => {
                     let message = match e {
                         Named::Enter => EventMsg::Enter,
+                        Named::Escape if !self.selected.is_empty() => {
+                            self.clear_selection();
+                            EventMsg::None
+                        }
                         Named::Escape => EventMsg::Quit,
+                        // like a ctrl + click
+                        Named::Space if self.modifiers.control() => {
+                            if let Some(entry) = self.db.either_iter().nth(self.focused) {
+                                return task_message(AppMsg::Selection(SelectionMsg::Toggle(
+                                    entry.id(),
+                                )));
+                            }
+                            EventMsg::None
+                        }
+                        Named::Delete if !self.selected.is_empty() => {
+                            return task_message(AppMsg::Selection(SelectionMsg::Delete));
+                        }
                         Named::ArrowDown if !self.config.horizontal => EventMsg::Next,
                         Named::ArrowUp if !self.config.horizontal => EventMsg::Previous,
                         Named::ArrowLeft if self.config.horizontal => EventMsg::Previous,
@@ -431,10 +904,23 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
                     return task_message(AppMsg::Navigation(message));
                 }
                 EventMsg::Next => {
-                    return self.focus_next();
+                    let from = self.focused;
+                    let task = self.focus_next();
+                    if self.modifiers.shift() {
+                        self.select_range(from, self.focused);
+                    }
+                    return task;
                 }
                 EventMsg::Previous => {
-                    return self.focus_previous();
+                    let from = self.focused;
+                    let task = self.focus_previous();
+                    if self.modifiers.shift() {
+                        self.select_range(from, self.focused);
+                    }
+                    return task;
+                }
+                EventMsg::Modifiers(modifiers) => {
+                    self.modifiers = modifiers;
                 }
                 EventMsg::Enter => {
                     if matches!(
@@ -443,12 +929,10 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
                             kind: PopupKind::Popup,
                             ..
                         })
-                    ) && let Some(data) = self.db.get(self.focused)
+                    ) && let Some(entry) = self.db.either_iter().nth(self.focused)
                     {
-                        return Task::batch([
-                            copy_iced(data.raw_content().clone()),
-                            self.close_popup(),
-                        ]);
+                        // counted and notified like a click
+                        return task_message(AppMsg::Copy(entry.id()));
                     }
                 }
                 EventMsg::Quit => {
@@ -456,18 +940,18 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
                 }
                 EventMsg::None => {}
             },
-            AppMsg::Db(inner) => {
-                if let Err(err) = block_on(self.db.handle_message(inner)) {
-                    error!("{err}");
-                }
-            }
+            AppMsg::Db(inner) => match block_on(self.db.handle_message(inner)) {
+                Ok(true) => return self.search(),
+                Ok(false) => {}
+                Err(err) => error!("{err}"),
+            },
             AppMsg::ReturnToClipboard => {
                 self.qr_code.take();
             }
             AppMsg::Config(msg) => match msg {
                 ConfigMsg::PrivateMode(private_mode) => {
                     config_set!(private_mode, private_mode);
-                    PRIVATE_MODE.store(private_mode, atomic::Ordering::Relaxed);
+                    self.update_private_mode();
                 }
                 ConfigMsg::Horizontal(horizontal) => {
                     config_set!(horizontal, horizontal);
@@ -475,6 +959,52 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
                 ConfigMsg::UniqueSession(unique_session) => {
                     config_set!(unique_session, unique_session);
                 }
+                ConfigMsg::FuzzySearch(fuzzy_search) => {
+                    config_set!(fuzzy_search, fuzzy_search);
+                    self.db.set_search_mode(self.config.search_mode());
+                    return self.search();
+                }
+                ConfigMsg::TextRecognition(text_recognition) => {
+                    config_set!(text_recognition, text_recognition);
+
+                    if text_recognition {
+                        return self.queue_text_recognition();
+                    }
+                }
+                ConfigMsg::KeepClipboardAlive(keep_clipboard_alive) => {
+                    config_set!(keep_clipboard_alive, keep_clipboard_alive);
+                }
+                ConfigMsg::DuplicatesIgnoreWhitespace(duplicates_ignore_whitespace) => {
+                    config_set!(duplicates_ignore_whitespace, duplicates_ignore_whitespace);
+                    self.db.set_normalization(self.config.normalization());
+                }
+                ConfigMsg::DuplicatesIgnoreCase(duplicates_ignore_case) => {
+                    config_set!(duplicates_ignore_case, duplicates_ignore_case);
+                    self.db.set_normalization(self.config.normalization());
+                }
+                ConfigMsg::KeepCopiedPosition(keep_copied_position) => {
+                    config_set!(keep_copied_position, keep_copied_position);
+                }
+                ConfigMsg::NewEntriesBadge(new_entries_badge) => {
+                    config_set!(new_entries_badge, new_entries_badge);
+                }
+                ConfigMsg::NotifyErrors(notify_errors) => {
+                    config_set!(notify_errors, notify_errors);
+                }
+                ConfigMsg::NotifyFiltered(notify_filtered) => {
+                    config_set!(notify_filtered, notify_filtered);
+                }
+                ConfigMsg::NotifyCopied(notify_copied) => {
+                    config_set!(notify_copied, notify_copied);
+                }
+                ConfigMsg::ConcatenationSeparator(concatenation_separator) => {
+                    config_set!(concatenation_separator, concatenation_separator);
+                }
+                ConfigMsg::SortByUsage(sort_by_usage) => {
+                    config_set!(sort_by_usage, sort_by_usage);
+                    self.db.set_sort_order(self.config.sort_order());
+                    return self.search();
+                }
             },
             AppMsg::NextPage => {
                 self.page += 1;
@@ -489,11 +1019,13 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
                     if let Err(err) = block_on(self.db.remove_favorite(entry)) {
                         error!("{err}");
                     }
+                    return self.search();
                 }
                 ContextMenuMsg::AddFavorite(entry) => {
                     if let Err(err) = block_on(self.db.add_favorite(entry, None)) {
                         error!("{err}");
                     }
+                    return self.search();
                 }
                 ContextMenuMsg::ShowQrCode(id) => {
                     match self.db.get_from_id(id) {
@@ -521,10 +1053,33 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
                         None => error!("id not found"),
                     }
                 }
+                ContextMenuMsg::CopyRecognizedText(id) => {
+                    if let Some(text) = self
+                        .db
+                        .get_from_id(id)
+                        .and_then(|entry| entry.recognized_text())
+                    {
+                        let data = ["text/plain;charset=utf-8", "text/plain"]
+                            .into_iter()
+                            .map(|mime| (mime.to_string(), text.as_bytes().to_vec()))
+                            .collect();
+
+                        return self.copy(data, false);
+                    }
+                }
+                ContextMenuMsg::CopyToPrimary(id) => match self.db.get_from_id(id) {
+                    Some(entry) => {
+                        let data = entry.raw_content().clone();
+                        return Task::batch([self.copy(data, true), self.close_popup()]);
+                    }
+                    None => error!("id not found"),
+                },
                 ContextMenuMsg::Delete(id) => {
                     if let Err(e) = block_on(self.db.delete(id)) {
                         error!("can't delete {}: {}", id, e);
+                        return self.search();
                     }
+                    return Task::batch([self.search(), self.undo_toast(fl!("entry_deleted"))]);
                 }
             },
             AppMsg::LinkClicked(url) => {
@@ -541,9 +1096,18 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
         let icon = self
             .core
             .applet
-            .icon_button(constcat::concat!(APPID, "-symbolic"))
+            .icon_button(self.panel_icon())
             .on_press(AppMsg::TogglePopup);
 
+        let icon: Element<_> = match self.panel_label() {
+            Some(label) => row()
+                .push(icon)
+                .push(self.core.applet.text(label))
+                .align_y(iced::Alignment::Center)
+                .into(),
+            None => icon.into(),
+        };
+
         MouseArea::new(icon)
             .on_right_release(AppMsg::ToggleQuickSettings)
             .into()
@@ -559,7 +1123,10 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
             PopupKind::QuickSettings => self.quick_settings_view(),
         };
 
-        self.core.applet.popup_container(view).into()
+        self.core
+            .applet
+            .popup_container(widget::toaster(&self.toasts, view))
+            .into()
     }
     fn subscription(&self) -> Subscription<Self::Message> {
         pub fn db_sub() -> Subscription<DbMessage> {
@@ -572,18 +1139,25 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
             db_sub().map(AppMsg::Db),
         ];
 
-        if !self.clipboard_state.is_error() {
-            subscriptions.push(Subscription::run(|| {
-                clipboard::sub().map(AppMsg::ClipboardEvent)
-            }));
+        // also updates the remaining time on the icon
+        if let Some(Pause::Until(_)) = self.pause {
+            subscriptions.push(
+                cosmic::iced::time::every(Duration::from_millis(1000)).map(|_| AppMsg::PauseTick),
+            );
         }
 
+        // a new id restarts the subscription, without waiting for the backoff
+        subscriptions.push(Subscription::run_with_id(
+            ("clipboard", self.clipboard_retry),
+            clipboard::sub().map(AppMsg::ClipboardEvent),
+        ));
+
         Subscription::batch(subscriptions)
     }
 
     fn on_app_exit(&mut self) -> Option<Self::Message> {
         if self.config.unique_session
-            && let Err(err) = block_on(self.db.clear())
+            && let Err(err) = block_on(self.db.clear(false))
         {
             error!("{err}");
         }
@@ -595,7 +1169,22 @@ impl<Db: DbTrait + 'static> cosmic::Application for AppState<Db> {
     }
 }
 
-// used because wl_clipboard can't copy when zwlr_data_control_manager_v1 is not there
+fn notify(summary: String, body: String) -> Task<AppMsg> {
+    Task::future(notification::send(summary, body)).discard()
+}
+
+/// First line of the text, or the mime type.
+fn preview(entry: &impl EntryTrait) -> String {
+    match entry.plain_text() {
+        Some(text) => utils::formatted_value(text, 1, 100).into_owned(),
+        None => entry
+            .preferred_content(&[])
+            .map(|((mime_type, _), _)| mime_type.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+// used when the clipboard backend can't set the selection, like on X11
 fn copy_iced(data: MimeDataMap) -> Task<AppMsg> {
     struct MimeDataMapN(MimeDataMap);
 
@@ -611,5 +1200,5 @@ fn copy_iced(data: MimeDataMap) -> Task<AppMsg> {
         }
     }
 
-    cosmic::iced::clipboard::write_data(MimeDataMapN(data))
+    cosmic::iced::clipboard::write_data(MimeDataMapN(clipboard::own_offer(data)))
 }
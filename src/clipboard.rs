@@ -1,25 +1,53 @@
 use std::{
-    io::Read,
+    collections::HashSet,
+    io::{self, PipeReader, Write},
     sync::{
-        Arc,
+        Arc, RwLock,
         atomic::{self},
     },
+    time::{Duration, Instant},
 };
 
-use cosmic::iced::{futures::SinkExt, stream::channel};
-use futures::Stream;
+use anyhow::Result;
+use cosmic::iced::{
+    futures::{SinkExt, channel::mpsc::Sender},
+    stream::channel,
+};
+use futures::{Stream, StreamExt, stream::FuturesUnordered};
 use itertools::Itertools;
-use tokio::sync::mpsc;
+use regex::Regex;
+use tokio::{io::AsyncReadExt, net::unix::pipe, sync::mpsc};
+
+use crate::{
+    clipboard_watcher,
+    config::{Config, PRIVATE_MODE},
+    db::{self, DbTrait, EntryHash, EntryId, EntryTrait, MimeDataMap},
+    x11_watcher,
+};
 
-use crate::{clipboard_watcher, config::PRIVATE_MODE, db::MimeDataMap};
+/// Between the reports of filtered offers, in ms.
+pub const FILTERED_REPORT_INTERVAL: i64 = 30 * 1000;
+
+/// Offered with our own selections, so they are not captured again.
+pub const OWN_MIME_TYPE: &str = "application/x-cosmic-ext-clipboard-manager";
+
+/// Tag `data` as offered by us.
+pub fn own_offer(mut data: MimeDataMap) -> MimeDataMap {
+    data.insert(OWN_MIME_TYPE.into(), Vec::new());
+    data
+}
 
 #[derive(Debug, Clone)]
 pub enum ClipboardMessage {
-    Connected,
+    /// With a handle to set the selection, if the backend can.
+    Connected(Option<SelectionHandle>),
     Data(MimeDataMap),
     /// Means that the source was closed, or the compurer just started
     /// This means the clipboard manager must become the source, by providing the last entry
     EmptyKeyboard,
+    /// None of the mime types of the offer is allowed by the filters,
+    /// or within the size and time limits
+    Filtered,
     Error(ClipboardError),
 }
 
@@ -27,118 +55,717 @@ pub enum ClipboardMessage {
 pub enum ClipboardError {
     #[error(transparent)]
     Watch(Arc<clipboard_watcher::Error>),
+    #[error("The clipboard watcher stopped unexpectedly")]
+    Stopped,
+}
+
+impl ClipboardError {
+    /// Reconnecting will not fix it.
+    fn is_permanent(&self) -> bool {
+        match self {
+            ClipboardError::Watch(e) => matches!(
+                **e,
+                clipboard_watcher::Error::MissingProtocol { .. }
+                    | clipboard_watcher::Error::PrimarySelectionUnsupported
+            ),
+            ClipboardError::Stopped => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ClipboardState {
+    #[default]
+    Init,
+    Connected,
+    Error(ErrorState),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorState {
+    MissingDataControlProtocol,
+    Other(String),
+}
+
+impl ClipboardState {
+    /// Return true when the clipboard just failed, not for each reconnection attempt.
+    pub fn update(&mut self, message: &ClipboardMessage) -> bool {
+        let was_error = matches!(self, ClipboardState::Error(_));
+
+        match message {
+            ClipboardMessage::Connected(_) => *self = ClipboardState::Connected,
+            ClipboardMessage::Error(e) => *self = ClipboardState::Error(e.into()),
+            ClipboardMessage::Data(_)
+            | ClipboardMessage::EmptyKeyboard
+            | ClipboardMessage::Filtered => {}
+        }
+
+        !was_error && matches!(self, ClipboardState::Error(_))
+    }
+}
+
+impl From<&ClipboardError> for ErrorState {
+    fn from(e: &ClipboardError) -> Self {
+        if let ClipboardError::Watch(e) = e
+            && let clipboard_watcher::Error::MissingProtocol { name, .. } = **e
+            && matches!(
+                name,
+                "ext_data_control_manager_v1" | "zwlr_data_control_manager_v1"
+            )
+        {
+            ErrorState::MissingDataControlProtocol
+        } else {
+            ErrorState::Other(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod mock;
+
+/// Keep the clipboard alive, by offering the last captured entry again
+/// when the application it was copied from exits.
+#[derive(Debug, Default)]
+pub struct KeepAlive {
+    last_captured: Option<EntryId>,
+}
+
+impl KeepAlive {
+    pub fn captured(&mut self, id: EntryId) {
+        self.last_captured = Some(id);
+    }
+
+    /// Store a captured offer, at `time` in ms.
+    pub async fn capture<Db: DbTrait>(
+        &mut self,
+        db: &mut Db,
+        data: MimeDataMap,
+        time: i64,
+    ) -> Result<Option<EntryId>> {
+        let id = db.insert_with_time(data, time).await?;
+
+        if let Some(id) = id {
+            self.captured(id);
+        }
+
+        Ok(id)
+    }
+
+    /// Content to offer when the clipboard became empty. Before any capture,
+    /// or when the entry was deleted, this is the most recent entry.
+    pub fn content<Db: DbTrait>(&self, db: &Db) -> Option<MimeDataMap> {
+        self.last_captured
+            .and_then(|id| db.get_from_id(id))
+            .or_else(|| db.last())
+            .map(|entry| entry.raw_content().clone())
+    }
+}
+
+/// Handle the messages of the clipboard subscription. What depends on the UI is
+/// left to the applet, see [`Handled`].
+#[derive(Debug, Default)]
+pub struct ClipboardHandler {
+    pub state: ClipboardState,
+    /// Set the selection without a focused surface, when the backend can
+    pub selection: Option<SelectionHandle>,
+    pub keep_alive: KeepAlive,
+    /// Not failed again until an offer is captured, for a flapping watcher
+    failure_reported: bool,
+    /// Of the last filtered offer reported, in ms
+    filtered_reported: Option<i64>,
+}
+
+/// What is left to do after a message of the clipboard.
+#[derive(Debug)]
+pub enum Handled {
+    Nothing,
+    /// The offer was stored, see [`DbTrait::insert`]
+    Captured(Option<EntryId>),
+    /// The clipboard became empty, offer this content again
+    Restore(MimeDataMap),
+    /// The clipboard just failed, not again until an offer is captured
+    Failed(ClipboardError),
+    /// At most once per [`FILTERED_REPORT_INTERVAL`]
+    Filtered,
+}
+
+impl ClipboardHandler {
+    /// Store the captured offers at `time` in ms, and restore the last one when the
+    /// clipboard becomes empty if `keep_alive`.
+    pub async fn handle<Db: DbTrait>(
+        &mut self,
+        db: &mut Db,
+        message: ClipboardMessage,
+        time: i64,
+        keep_alive: bool,
+    ) -> Result<Handled> {
+        let failed = self.state.update(&message);
+
+        let handled = match message {
+            ClipboardMessage::Connected(selection) => {
+                self.selection = selection;
+                Handled::Nothing
+            }
+            ClipboardMessage::Data(data) => {
+                self.failure_reported = false;
+                Handled::Captured(self.keep_alive.capture(db, data, time).await?)
+            }
+            // logged by the subscription, which reconnects by itself
+            ClipboardMessage::Error(e) => {
+                self.selection = None;
+
+                if failed && !self.failure_reported {
+                    self.failure_reported = true;
+                    Handled::Failed(e)
+                } else {
+                    Handled::Nothing
+                }
+            }
+            ClipboardMessage::Filtered => {
+                if self
+                    .filtered_reported
+                    .is_some_and(|reported| time - reported < FILTERED_REPORT_INTERVAL)
+                {
+                    Handled::Nothing
+                } else {
+                    self.filtered_reported = Some(time);
+                    Handled::Filtered
+                }
+            }
+            ClipboardMessage::EmptyKeyboard => {
+                match keep_alive.then(|| self.keep_alive.content(db)).flatten() {
+                    Some(data) => Handled::Restore(data),
+                    None => Handled::Nothing,
+                }
+            }
+        };
+
+        Ok(handled)
+    }
+}
+
+/// Access to the clipboard of the system.
+pub trait ClipboardBackend: Send + 'static {
+    /// Block until the selection changes, then start the transfer of the mime types
+    /// of the new offer selected by `policy`. The pipes are read with [`read_offer`].
+    fn watch(
+        &mut self,
+        policy: &ReadPolicy,
+    ) -> Result<Vec<(String, PipeReader)>, clipboard_watcher::Error>;
+
+    /// Handle to set the selection while watching, [`None`] if the backend can't.
+    fn selection(&self) -> Option<SelectionHandle>;
+}
+
+/// Become the source of the selection, from any thread.
+pub trait SetSelection: Send + Sync + 'static {
+    /// Offer `data` as the selection, or as the primary selection.
+    fn set_selection(
+        &self,
+        data: MimeDataMap,
+        primary: bool,
+    ) -> Result<(), clipboard_watcher::Error>;
+}
+
+#[derive(Clone)]
+pub struct SelectionHandle(Arc<dyn SetSelection>);
+
+impl SelectionHandle {
+    pub fn new(setter: impl SetSelection) -> Self {
+        Self(Arc::new(setter))
+    }
+
+    pub fn set_selection(
+        &self,
+        data: MimeDataMap,
+        primary: bool,
+    ) -> Result<(), clipboard_watcher::Error> {
+        self.0.set_selection(own_offer(data), primary)
+    }
+}
+
+impl std::fmt::Debug for SelectionHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SelectionHandle").finish_non_exhaustive()
+    }
+}
+
+impl ClipboardBackend for clipboard_watcher::Watcher {
+    fn watch(
+        &mut self,
+        policy: &ReadPolicy,
+    ) -> Result<Vec<(String, PipeReader)>, clipboard_watcher::Error> {
+        self.start_watching(clipboard_watcher::Seat::Unspecified, policy)
+    }
+
+    fn selection(&self) -> Option<SelectionHandle> {
+        Some(SelectionHandle::new(self.selection_setter()))
+    }
+}
+
+impl SetSelection for clipboard_watcher::SelectionSetter {
+    fn set_selection(
+        &self,
+        data: MimeDataMap,
+        primary: bool,
+    ) -> Result<(), clipboard_watcher::Error> {
+        self.set_selection(data, primary)
+    }
+}
+
+/// Set the selection from a process without the applet, and block until another copy
+/// replaces it. Wayland only.
+pub fn serve_selection(data: MimeDataMap, primary: bool) -> Result<(), clipboard_watcher::Error> {
+    clipboard_watcher::Watcher::init()?.serve_selection(own_offer(data), primary)
+}
+
+/// Pipe transferring `content`, for the backends which read it themselves.
+pub fn pipe_from(content: Vec<u8>) -> io::Result<PipeReader> {
+    let (reader, mut writer) = std::io::pipe()?;
+
+    // the content can be bigger than the pipe buffer
+    std::thread::spawn(move || writer.write_all(&content));
+
+    Ok(reader)
+}
+
+/// Which mime types of an offer are read, and how.
+#[derive(Debug, Clone)]
+pub struct ReadPolicy {
+    /// For the whole offer, its mime types being read concurrently.
+    pub timeout: Duration,
+    /// Skip the mime types with more content than this.
+    pub max_bytes: Option<u64>,
+    /// Only read the mime types matching one of them, or all of them when empty.
+    pub allowed: Vec<Regex>,
+    pub ignored: Vec<Regex>,
+}
+
+/// Updated from the config, like [`PRIVATE_MODE`].
+pub static READ_POLICY: RwLock<ReadPolicy> = RwLock::new(ReadPolicy::DEFAULT);
+
+impl ReadPolicy {
+    const DEFAULT: ReadPolicy = ReadPolicy {
+        timeout: Duration::from_secs(5),
+        max_bytes: Some(100 << 20),
+        allowed: Vec::new(),
+        ignored: Vec::new(),
+    };
+
+    pub fn new(config: &Config) -> Self {
+        let regexes = |patterns: &[String]| {
+            patterns
+                .iter()
+                .filter_map(|r| match Regex::new(r) {
+                    Ok(r) => Some(r),
+                    Err(e) => {
+                        error!("regex {e}");
+                        None
+                    }
+                })
+                .collect()
+        };
+
+        Self {
+            max_bytes: config
+                .maximum_mime_type_size
+                .map(|size| size.saturating_mul(1 << 20)),
+            allowed: regexes(&config.allowed_mime_types),
+            ignored: regexes(&config.ignored_mime_types),
+            ..Self::DEFAULT
+        }
+    }
+
+    fn is_allowed(&self, mime_type: &str) -> bool {
+        (self.allowed.is_empty() || self.allowed.iter().any(|r| r.is_match(mime_type)))
+            && !self.ignored.iter().any(|r| r.is_match(mime_type))
+    }
+
+    /// Mime types of the offer worth reading, none for our own offers.
+    /// [`clipboard_watcher::Error::Filtered`] if none of the offered ones is allowed.
+    pub fn select(
+        &self,
+        mime_types: impl IntoIterator<Item = String>,
+    ) -> Result<Vec<String>, clipboard_watcher::Error> {
+        let mime_types = mime_types.into_iter().collect_vec();
+
+        if mime_types
+            .iter()
+            .any(|mime_type| mime_type == OWN_MIME_TYPE)
+        {
+            debug!("own offer");
+            return Ok(Vec::new());
+        }
+
+        let offered = !mime_types.is_empty();
+
+        let allowed = mime_types
+            .into_iter()
+            .filter(|mime_type| {
+                let allowed = self.is_allowed(mime_type);
+                if !allowed {
+                    debug!("ignored mime type: {mime_type}");
+                }
+                allowed
+            })
+            .collect_vec();
+
+        if offered && allowed.is_empty() {
+            return Err(clipboard_watcher::Error::Filtered);
+        }
+
+        Ok(allowed
+            .iter()
+            .filter(|mime_type| {
+                let redundant = is_redundant(mime_type, &allowed);
+                if redundant {
+                    debug!("redundant mime type: {mime_type}");
+                }
+                !redundant
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+impl Default for ReadPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Whether the source converts the content of another mime type of the offer to this one.
+fn is_redundant(mime_type: &str, offered: &[String]) -> bool {
+    let is_offered = |other: &str| offered.iter().any(|m| m == other);
+
+    match mime_type {
+        // every image format the toolkit knows, converted from the same pixels
+        "image/png" => false,
+        m if m.starts_with("image/") && !m.starts_with("image/svg") => is_offered("image/png"),
+        // legacy X11 targets, in the encoding of the locale
+        "STRING" | "TEXT" | "COMPOUND_TEXT" => {
+            is_offered("UTF8_STRING") || is_offered("text/plain;charset=utf-8")
+        }
+        _ => false,
+    }
+}
+
+/// Read a pipe without blocking a thread, so it is closed when dropped on a timeout.
+async fn read_pipe(pipe: PipeReader, max_bytes: Option<u64>) -> io::Result<Vec<u8>> {
+    let mut pipe = pipe::Receiver::from_owned_fd(pipe.into())?;
+    let mut contents = Vec::new();
+
+    match max_bytes {
+        Some(max_bytes) => {
+            (&mut pipe)
+                .take(max_bytes.saturating_add(1))
+                .read_to_end(&mut contents)
+                .await?;
+
+            if contents.len() as u64 > max_bytes {
+                return Err(io::Error::new(
+                    io::ErrorKind::FileTooLarge,
+                    format!("more than {max_bytes} bytes"),
+                ));
+            }
+        }
+        None => {
+            pipe.read_to_end(&mut contents).await?;
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Read the content of each mime type, skipping the empty ones, the ones bigger
+/// than the limit, and the ones not transferred before the timeout.
+/// [`clipboard_watcher::Error::Filtered`] if these limits left nothing.
+pub async fn read_offer(
+    offer: Vec<(String, PipeReader)>,
+    policy: &ReadPolicy,
+) -> Result<MimeDataMap, clipboard_watcher::Error> {
+    let mut pending = HashSet::new();
+    let mut reads = FuturesUnordered::new();
+
+    for (mime_type, pipe) in offer {
+        pending.insert(mime_type.clone());
+
+        let max_bytes = policy.max_bytes;
+        reads.push(async move { (mime_type, read_pipe(pipe, max_bytes).await) });
+    }
+
+    let deadline = tokio::time::Instant::now() + policy.timeout;
+    let mut data = MimeDataMap::new();
+    let mut limited = false;
+
+    loop {
+        let (mime_type, res) = match tokio::time::timeout_at(deadline, reads.next()).await {
+            Ok(Some(read)) => read,
+            Ok(None) => break,
+            // the pipes still read are closed with `reads`
+            Err(_) => {
+                warn!("timeout on external pipe clipboard: {pending:?}");
+                limited = true;
+                break;
+            }
+        };
+
+        pending.remove(&mime_type);
+
+        match res {
+            Ok(contents) => {
+                if contents.is_empty() {
+                    debug!("data is empty: {mime_type}");
+                } else {
+                    data.insert(mime_type, contents);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::FileTooLarge => {
+                debug!("{mime_type}: {e}");
+                limited = true;
+            }
+            Err(e) => {
+                warn!("read error on external pipe clipboard: {mime_type} {e}");
+            }
+        }
+    }
+
+    if data.is_empty() && limited {
+        return Err(clipboard_watcher::Error::Filtered);
+    }
+
+    Ok(data)
+}
+
+/// Delay before reconnecting, doubled after each failed attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+const BACKOFF: Backoff = Backoff {
+    initial: Duration::from_secs(1),
+    max: Duration::from_secs(60),
+};
+
+impl Backoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max)
+    }
 }
 
 enum WatchRes<I> {
     Some(I),
     None,
+    Filtered,
     Err(clipboard_watcher::Error),
 }
 
+/// Delay under which the same offer captured by both watchers was bridged by XWayland.
+const BRIDGE_DELAY: Duration = Duration::from_secs(2);
+
 pub fn sub() -> impl Stream<Item = ClipboardMessage> {
-    channel(500, async |mut output| {
-        match clipboard_watcher::Watcher::init() {
-            Ok(mut clipboard_watcher) => {
-                let (tx, mut rx) = mpsc::channel(5);
-
-                tokio::task::spawn_blocking(move || {
-                    loop {
-                        debug!("start watching");
-                        match clipboard_watcher.start_watching(clipboard_watcher::Seat::Unspecified)
-                        {
-                            Ok(res) => {
-                                if !PRIVATE_MODE.load(atomic::Ordering::Relaxed) {
-                                    tx.blocking_send(WatchRes::Some(res)).unwrap();
-                                } else {
-                                    info!("private mode")
-                                }
-                            }
-                            Err(e) => match e {
-                                clipboard_watcher::Error::ClipboardEmpty => {
-                                    tx.blocking_send(WatchRes::None).unwrap();
-                                }
-                                _ => {
-                                    tx.blocking_send(WatchRes::Err(e)).unwrap();
-                                    break;
-                                }
-                            },
-                        }
-                    }
-                });
-                output.send(ClipboardMessage::Connected).await.unwrap();
-
-                let mut i = 0;
-                loop {
-                    let s = debug_span!("", i);
-                    let _s = s.enter();
-                    i += 1;
-
-                    match rx.recv().await {
-                        Some(WatchRes::Some(res)) => {
-                            let mut data = MimeDataMap::new();
-
-                            for (mime_type, mut pipe) in res {
-                                let mut contents = Vec::new();
-
-                                match pipe.read_to_end(&mut contents) {
-                                    Ok(len) => {
-                                        if len == 0 {
-                                            debug!("data is empty: {mime_type}");
-                                        } else {
-                                            data.insert(mime_type, contents);
-                                        }
-                                    }
-                                    Err(e) => {
-                                        warn!(
-                                            "read error on external pipe clipboard: {mime_type} {e}"
-                                        );
-                                    }
-                                }
-                            }
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let x11 = std::env::var_os("DISPLAY").is_some();
 
-                            if !data.is_empty() {
-                                let mimes = data
-                                    .iter()
-                                    .map(|(m, d)| (m.to_string(), d.len()))
-                                    .collect_vec();
+    match (wayland, x11) {
+        (false, true) => {
+            info!("no wayland display, watch the X11 clipboard");
+            supervise(x11_watcher::Watcher::init, BACKOFF).boxed()
+        }
+        (true, true) => {
+            info!("also watch the X11 clipboard, for the XWayland applications");
+            merge(
+                supervise(clipboard_watcher::Watcher::init, BACKOFF),
+                supervise(x11_watcher::Watcher::init, BACKOFF),
+                BRIDGE_DELAY,
+            )
+            .boxed()
+        }
+        _ => supervise(clipboard_watcher::Watcher::init, BACKOFF).boxed(),
+    }
+}
 
-                                debug!("send mime types to db: {mimes:?}");
-                                output.send(ClipboardMessage::Data(data)).await.unwrap();
-                            }
-                        }
+/// Add the offers captured by a secondary watcher. The offers bridged between
+/// both clipboards are only forwarded once, when captured in less than `delay`.
+pub(crate) fn merge(
+    primary: impl Stream<Item = ClipboardMessage> + Send + 'static,
+    secondary: impl Stream<Item = ClipboardMessage> + Send + 'static,
+    delay: Duration,
+) -> impl Stream<Item = ClipboardMessage> + Send + 'static {
+    // the state and the selection handle are the ones of the primary watcher,
+    // the errors of the secondary one are only logged
+    let secondary = secondary.filter(|message| {
+        if let ClipboardMessage::Error(e) = message {
+            let retry = if e.is_permanent() {
+                "not retried"
+            } else {
+                "retried"
+            };
+            warn!("secondary clipboard watcher, {retry}: {e}");
+        }
 
-                        Some(WatchRes::None) => {
-                            debug!("empty keyboard");
-                            output.send(ClipboardMessage::EmptyKeyboard).await.unwrap();
-                        }
-                        Some(WatchRes::Err(e)) => {
-                            output
-                                .send(ClipboardMessage::Error(ClipboardError::Watch(e.into())))
-                                .await
-                                .unwrap();
-                            std::future::pending::<()>().await;
-                        }
-                        None => {
-                            std::future::pending::<()>().await;
-                        }
-                    }
+        std::future::ready(matches!(message, ClipboardMessage::Data(_)))
+    });
+
+    let mut recent: Vec<(EntryHash, Instant)> = Vec::new();
+
+    futures::stream::select(primary, secondary).filter(move |message| {
+        let forward = match message {
+            ClipboardMessage::Data(data) => {
+                let now = Instant::now();
+                recent.retain(|(_, time)| now.duration_since(*time) < delay);
+
+                let hash = db::entry_hash(data);
+                let bridged = recent.iter().any(|(recent, _)| *recent == hash);
+                if bridged {
+                    debug!("offer bridged by XWayland");
                 }
+
+                recent.push((hash, now));
+                !bridged
             }
+            _ => true,
+        };
 
-            Err(e) => {
-                // todo: how to cancel properly?
-                // https://github.com/pop-os/cosmic-files/blob/d96d48995d49e17f01903ca4d89839eb4a1b1104/src/app.rs#L1704
-                output
-                    .send(ClipboardMessage::Error(ClipboardError::Watch(e.into())))
-                    .await
-                    .unwrap();
+        std::future::ready(forward)
+    })
+}
+
+/// Connect to the clipboard, and reconnect with an exponential backoff when it fails.
+/// The backoff is only reset once an offer was captured, so a watcher failing right
+/// after connecting is not retried every second.
+/// Permanent errors are only retried when the stream is recreated.
+pub(crate) fn supervise<B: ClipboardBackend>(
+    mut connect: impl FnMut() -> Result<B, clipboard_watcher::Error> + Send + 'static,
+    backoff: Backoff,
+) -> impl Stream<Item = ClipboardMessage> {
+    channel(500, async move |mut output| {
+        let mut attempt = 0;
+
+        loop {
+            let error = match connect() {
+                Ok(backend) => match forward(backend, &mut output, &mut attempt).await {
+                    Some(error) => error,
+                    None => return,
+                },
+                Err(e) => ClipboardError::Watch(e.into()),
+            };
 
+            error!("clipboard: {error}");
+
+            let permanent = error.is_permanent();
+
+            if output.send(ClipboardMessage::Error(error)).await.is_err() {
+                return;
+            }
+
+            if permanent {
                 std::future::pending::<()>().await;
             }
-        };
+
+            let delay = backoff.delay(attempt);
+            info!("reconnect to the clipboard in {delay:?}");
+            tokio::time::sleep(delay).await;
+            attempt = attempt.saturating_add(1);
+        }
     })
 }
 
+/// Forward the offers until the watcher fails, resetting the reconnection `attempt`
+/// on each of them. Return [`None`] if the receiver was dropped.
+async fn forward<B: ClipboardBackend>(
+    mut backend: B,
+    output: &mut Sender<ClipboardMessage>,
+    attempt: &mut u32,
+) -> Option<ClipboardError> {
+    let (tx, mut rx) = mpsc::channel(5);
+    let selection = backend.selection();
+
+    tokio::task::spawn_blocking(move || {
+        loop {
+            debug!("start watching");
+            let policy = READ_POLICY.read().unwrap().clone();
+            let res = match backend.watch(&policy) {
+                Ok(res) => {
+                    if PRIVATE_MODE.load(atomic::Ordering::Relaxed) {
+                        info!("private mode");
+                        continue;
+                    }
+                    WatchRes::Some(res)
+                }
+                Err(clipboard_watcher::Error::ClipboardEmpty) => WatchRes::None,
+                Err(clipboard_watcher::Error::Filtered) => {
+                    if PRIVATE_MODE.load(atomic::Ordering::Relaxed) {
+                        continue;
+                    }
+                    WatchRes::Filtered
+                }
+                Err(e) => WatchRes::Err(e),
+            };
+
+            let is_err = matches!(res, WatchRes::Err(_));
+
+            // the receiver is dropped with the subscription
+            if tx.blocking_send(res).is_err() || is_err {
+                break;
+            }
+        }
+    });
+
+    output
+        .send(ClipboardMessage::Connected(selection))
+        .await
+        .ok()?;
+
+    let mut i = 0;
+    loop {
+        let s = debug_span!("", i);
+        let _s = s.enter();
+        i += 1;
+
+        let message = match rx.recv().await {
+            Some(WatchRes::Some(res)) => {
+                let policy = READ_POLICY.read().unwrap().clone();
+
+                // the watcher keeps dispatching meanwhile, to send our own selection
+                match read_offer(res, &policy).await {
+                    Ok(data) if data.is_empty() => continue,
+                    Ok(data) => {
+                        let mimes = data
+                            .iter()
+                            .map(|(m, d)| (m.to_string(), d.len()))
+                            .collect_vec();
+
+                        debug!("send mime types to db: {mimes:?}");
+                        *attempt = 0;
+                        ClipboardMessage::Data(data)
+                    }
+                    Err(_) => {
+                        debug!("offer over the limits");
+                        ClipboardMessage::Filtered
+                    }
+                }
+            }
+            Some(WatchRes::None) => {
+                debug!("empty keyboard");
+                ClipboardMessage::EmptyKeyboard
+            }
+            Some(WatchRes::Filtered) => {
+                debug!("filtered offer");
+                ClipboardMessage::Filtered
+            }
+            Some(WatchRes::Err(e)) => return Some(ClipboardError::Watch(e.into())),
+            None => return Some(ClipboardError::Stopped),
+        };
+
+        output.send(message).await.ok()?;
+    }
+}
+
 // unfold experiment, doesn't work with channel, but better error management
 /*
 
@@ -190,3 +817,470 @@ pub fn sub2() -> Subscription<Message> {
 }
 
  */
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+    use serial_test::serial;
+
+    use super::{mock::MockBackend, *};
+
+    const TEST_BACKOFF: Backoff = Backoff {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(4),
+    };
+
+    fn states(messages: &[ClipboardMessage]) -> Vec<ClipboardState> {
+        let mut state = ClipboardState::Init;
+
+        messages
+            .iter()
+            .map(|message| {
+                state.update(message);
+                state.clone()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn reconnect() {
+        let clipboard = MockBackend::default();
+        clipboard.copy(MimeDataMap::from([("text/plain".into(), b"hello".into())]));
+        clipboard.clear();
+        clipboard.disconnect();
+
+        let mut attempts = 0;
+
+        let connect = {
+            let clipboard = clipboard.clone();
+            move || {
+                attempts += 1;
+                match attempts {
+                    1 => Err(clipboard_watcher::Error::NoSeats),
+                    _ => Ok(clipboard.clone()),
+                }
+            }
+        };
+
+        let messages = supervise(connect, TEST_BACKOFF)
+            .take(6)
+            .collect::<Vec<_>>()
+            .await;
+
+        // unblock the last watcher
+        clipboard.disconnect();
+
+        let no_seats = ClipboardState::Error(ErrorState::Other("There are no seats".into()));
+        let disconnected = ClipboardState::Error(ErrorState::Other(
+            "Couldn't connect to the Wayland compositor".into(),
+        ));
+
+        assert_eq!(
+            states(&messages),
+            vec![
+                no_seats,
+                ClipboardState::Connected,
+                ClipboardState::Connected,
+                ClipboardState::Connected,
+                disconnected,
+                ClipboardState::Connected,
+            ]
+        );
+        assert!(
+            matches!(&messages[2], ClipboardMessage::Data(data) if data["text/plain"] == b"hello")
+        );
+        assert!(matches!(messages[3], ClipboardMessage::EmptyKeyboard));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn flapping_watcher() {
+        let clipboard = MockBackend::default();
+
+        // connects, then fails right away
+        for _ in 0..4 {
+            clipboard.disconnect();
+        }
+
+        let connect = {
+            let clipboard = clipboard.clone();
+            move || Ok(clipboard.clone())
+        };
+
+        let backoff = Backoff {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(80),
+        };
+
+        let mut stream = std::pin::pin!(supervise(connect, backoff));
+        let mut errors = Vec::new();
+
+        while errors.len() < 4 {
+            if let ClipboardMessage::Error(_) = stream.next().await.unwrap() {
+                errors.push(Instant::now());
+            }
+        }
+
+        // not reset by the connections
+        let delays = errors
+            .windows(2)
+            .map(|times| times[1] - times[0])
+            .collect_vec();
+
+        for (delay, min) in delays.iter().zip([20, 40, 80]) {
+            assert!(*delay >= Duration::from_millis(min), "{delays:?}");
+        }
+
+        clipboard.disconnect();
+    }
+
+    #[test]
+    fn failures() {
+        let error = ClipboardMessage::Error(ClipboardError::Stopped);
+        let connected = ClipboardMessage::Connected(None);
+
+        let mut state = ClipboardState::Init;
+        let failed = [&error, &error, &connected, &error].map(|message| state.update(message));
+
+        assert_eq!(failed, [true, false, false, true]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn permanent_error() {
+        let connect = || {
+            Err::<MockBackend, _>(clipboard_watcher::Error::MissingProtocol {
+                name: "ext_data_control_manager_v1",
+                version: 1,
+            })
+        };
+
+        let mut stream = std::pin::pin!(supervise(connect, TEST_BACKOFF));
+
+        let messages = vec![stream.next().await.unwrap()];
+        assert_eq!(
+            states(&messages),
+            vec![ClipboardState::Error(
+                ErrorState::MissingDataControlProtocol
+            )]
+        );
+
+        // no reconnection
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), stream.next())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn big_offer() {
+        let image = vec![7; 1 << 20];
+
+        let clipboard = MockBackend::default();
+        clipboard.copy(MimeDataMap::from([
+            ("image/png".into(), image.clone()),
+            ("text/plain".into(), Vec::new()),
+        ]));
+
+        let policy = ReadPolicy::default();
+        let data = read_offer(clipboard.clone().watch(&policy).unwrap(), &policy)
+            .await
+            .unwrap();
+
+        assert_eq!(data, MimeDataMap::from([("image/png".into(), image)]));
+    }
+
+    #[tokio::test]
+    async fn read_timeout() {
+        // the source never writes, nor closes the pipe
+        let (stuck, mut writer) = std::io::pipe().unwrap();
+        let offer = vec![
+            ("image/png".into(), stuck),
+            ("text/plain".into(), pipe_from(b"hello".to_vec()).unwrap()),
+        ];
+
+        let policy = ReadPolicy {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let data = read_offer(offer, &policy).await.unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            data,
+            MimeDataMap::from([("text/plain".into(), b"hello".to_vec())])
+        );
+
+        // not read anymore
+        assert_eq!(
+            writer.write(b"late").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[tokio::test]
+    async fn read_max_bytes() {
+        let offer = vec![
+            ("image/png".into(), pipe_from(vec![7; 1025]).unwrap()),
+            ("image/bmp".into(), pipe_from(vec![7; 1024]).unwrap()),
+        ];
+
+        let policy = ReadPolicy {
+            max_bytes: Some(1024),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            read_offer(offer, &policy).await.unwrap(),
+            MimeDataMap::from([("image/bmp".into(), vec![7; 1024])])
+        );
+
+        // nothing left
+        let offer = vec![("image/png".into(), pipe_from(vec![7; 1025]).unwrap())];
+        assert!(matches!(
+            read_offer(offer, &policy).await,
+            Err(clipboard_watcher::Error::Filtered)
+        ));
+
+        // unlike an empty offer
+        let offer = vec![("text/plain".into(), pipe_from(Vec::new()).unwrap())];
+        assert_eq!(
+            read_offer(offer, &policy).await.unwrap(),
+            MimeDataMap::new()
+        );
+    }
+
+    #[test]
+    fn select_mime_types() {
+        let offer = |mime_types: &[&str]| mime_types.iter().map(|m| m.to_string()).collect_vec();
+
+        let policy = ReadPolicy::default();
+
+        // converted from the png
+        assert_eq!(
+            policy
+                .select(offer(&[
+                    "image/png",
+                    "image/jpeg",
+                    "image/bmp",
+                    "image/svg+xml"
+                ]))
+                .unwrap(),
+            offer(&["image/png", "image/svg+xml"])
+        );
+        assert_eq!(
+            policy.select(offer(&["image/jpeg", "image/bmp"])).unwrap(),
+            offer(&["image/jpeg", "image/bmp"])
+        );
+        assert_eq!(
+            policy
+                .select(offer(&["UTF8_STRING", "STRING", "TEXT", "text/plain"]))
+                .unwrap(),
+            offer(&["UTF8_STRING", "text/plain"])
+        );
+
+        let policy = ReadPolicy {
+            allowed: vec![
+                Regex::new("^text/").unwrap(),
+                Regex::new("^image/").unwrap(),
+            ],
+            ignored: vec![Regex::new("^image/png$").unwrap()],
+            ..Default::default()
+        };
+
+        // the jpeg is not redundant when the png is ignored
+        assert_eq!(
+            policy
+                .select(offer(&[
+                    "image/png",
+                    "image/jpeg",
+                    "text/html",
+                    "x-special/gnome-copied-files"
+                ]))
+                .unwrap(),
+            offer(&["image/jpeg", "text/html"])
+        );
+
+        assert!(matches!(
+            policy.select(offer(&["x-special/gnome-copied-files"])),
+            Err(clipboard_watcher::Error::Filtered)
+        ));
+
+        // our own offer
+        assert_eq!(
+            policy.select(offer(&["text/html", OWN_MIME_TYPE])).unwrap(),
+            offer(&[])
+        );
+    }
+
+    #[tokio::test]
+    async fn ignored_mime_types() {
+        let clipboard = MockBackend::default();
+        clipboard.copy(MimeDataMap::from([
+            ("text/plain".into(), b"hello".into()),
+            ("application/x-secret".into(), b"secret".into()),
+        ]));
+
+        let policy = ReadPolicy {
+            ignored: vec![Regex::new("secret").unwrap()],
+            ..Default::default()
+        };
+
+        let offer = clipboard.clone().watch(&policy).unwrap();
+
+        assert_eq!(
+            offer
+                .into_iter()
+                .map(|(mime_type, _)| mime_type)
+                .collect_vec(),
+            vec!["text/plain".to_string()]
+        );
+        clipboard.copy(MimeDataMap::from([(
+            "application/x-secret".into(),
+            b"secret".into(),
+        )]));
+
+        assert!(matches!(
+            clipboard.clone().watch(&policy),
+            Err(clipboard_watcher::Error::Filtered)
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn merged_watchers() {
+        let wayland = MockBackend::default();
+        let x11 = MockBackend::default();
+
+        let a = MimeDataMap::from([("text/plain".into(), b"a".into())]);
+        let b = MimeDataMap::from([("text/plain".into(), b"b".into())]);
+
+        // a bridged from X11, with the mime types of XWayland
+        x11.copy(MimeDataMap::from([("UTF8_STRING".into(), b"a".into())]));
+        wayland.copy(a.clone());
+        x11.copy(b.clone());
+        // the X11 connection fails
+        x11.disconnect();
+
+        let connect = |clipboard: &MockBackend| {
+            let clipboard = clipboard.clone();
+            move || Ok(clipboard.clone())
+        };
+
+        let messages = merge(
+            supervise(connect(&wayland), TEST_BACKOFF),
+            supervise(connect(&x11), TEST_BACKOFF),
+            Duration::from_secs(60),
+        );
+        let mut messages = std::pin::pin!(messages);
+
+        let mut connected = 0;
+        let mut captured = Vec::new();
+
+        while captured.len() < 2 {
+            match messages.next().await.unwrap() {
+                ClipboardMessage::Connected(Some(_)) => connected += 1,
+                ClipboardMessage::Data(data) => {
+                    captured.push(data.into_values().next().unwrap());
+                }
+                message => panic!("{message:?}"),
+            }
+        }
+
+        // a is only captured once
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), messages.next())
+                .await
+                .is_err()
+        );
+
+        captured.sort();
+        assert_eq!(connected, 1);
+        assert_eq!(captured, vec![b"a".to_vec(), b"b".to_vec()]);
+
+        // unblock the watchers
+        wayland.disconnect();
+        x11.disconnect();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn own_offers() {
+        let clipboard = MockBackend::default();
+        let connect = {
+            let clipboard = clipboard.clone();
+            move || Ok(clipboard.clone())
+        };
+
+        let mut stream = std::pin::pin!(supervise(connect, TEST_BACKOFF));
+
+        let Some(ClipboardMessage::Connected(Some(selection))) = stream.next().await else {
+            panic!("not connected");
+        };
+
+        let a = MimeDataMap::from([("text/plain".into(), b"a".into())]);
+        let b = MimeDataMap::from([("text/plain".into(), b"b".into())]);
+
+        selection.set_selection(a.clone(), false).unwrap();
+        assert_eq!(clipboard.selection(), Some(own_offer(a)));
+
+        // only the offer of another application is captured
+        clipboard.copy(b.clone());
+        assert!(matches!(stream.next().await, Some(ClipboardMessage::Data(data)) if data == b));
+
+        clipboard.disconnect();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn offer_over_the_limits() {
+        let clipboard = MockBackend::default();
+        let connect = {
+            let clipboard = clipboard.clone();
+            move || Ok(clipboard.clone())
+        };
+
+        // read by every test of the capture, which are serial
+        *READ_POLICY.write().unwrap() = ReadPolicy {
+            max_bytes: Some(4),
+            ..Default::default()
+        };
+
+        let mut stream = std::pin::pin!(supervise(connect, TEST_BACKOFF));
+        assert!(matches!(
+            stream.next().await,
+            Some(ClipboardMessage::Connected(_))
+        ));
+
+        clipboard.copy(MimeDataMap::from([("text/plain".into(), b"hello".into())]));
+        assert!(matches!(
+            stream.next().await,
+            Some(ClipboardMessage::Filtered)
+        ));
+
+        // an empty offer is skipped silently
+        clipboard.copy(MimeDataMap::from([("text/plain".into(), Vec::new())]));
+        clipboard.copy(MimeDataMap::from([("text/plain".into(), b"ok".into())]));
+        assert!(matches!(
+            stream.next().await,
+            Some(ClipboardMessage::Data(data)) if data["text/plain"] == b"ok"
+        ));
+
+        *READ_POLICY.write().unwrap() = ReadPolicy::default();
+        clipboard.disconnect();
+    }
+
+    #[test]
+    fn backoff() {
+        let delays = (0..8)
+            .map(|attempt| BACKOFF.delay(attempt).as_secs())
+            .collect::<Vec<_>>();
+
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(BACKOFF.delay(u32::MAX), BACKOFF.max);
+    }
+}
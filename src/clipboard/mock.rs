@@ -0,0 +1,98 @@
+//! In-memory clipboard, to test the capture without a compositor.
+
+use std::{
+    collections::VecDeque,
+    io::PipeReader,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use cosmic::cctk::wayland_client::ConnectError;
+
+use crate::{clipboard_watcher::Error, db::MimeDataMap};
+
+use super::{ClipboardBackend, ReadPolicy, SelectionHandle, SetSelection, pipe_from};
+
+enum Event {
+    Selection(Option<MimeDataMap>),
+    Disconnect,
+}
+
+#[derive(Default)]
+struct State {
+    events: VecDeque<Event>,
+    selection: Option<MimeDataMap>,
+}
+
+/// Clipboard shared by its clones, like the clients of a compositor.
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl MockBackend {
+    /// Another application copied `data`.
+    pub fn copy(&self, data: MimeDataMap) {
+        self.push(Event::Selection(Some(data)));
+    }
+
+    /// The source of the selection exited.
+    pub fn clear(&self) {
+        self.push(Event::Selection(None));
+    }
+
+    /// Make the next watch fail, like a lost connection.
+    pub fn disconnect(&self) {
+        self.push(Event::Disconnect);
+    }
+
+    pub fn selection(&self) -> Option<MimeDataMap> {
+        self.state.0.lock().unwrap().selection.clone()
+    }
+
+    fn push(&self, event: Event) {
+        let (state, condvar) = &*self.state;
+        let mut state = state.lock().unwrap();
+
+        if let Event::Selection(selection) = &event {
+            state.selection = selection.clone();
+        }
+
+        state.events.push_back(event);
+        condvar.notify_all();
+    }
+}
+
+impl ClipboardBackend for MockBackend {
+    fn watch(&mut self, policy: &ReadPolicy) -> Result<Vec<(String, PipeReader)>, Error> {
+        let (state, condvar) = &*self.state;
+        let mut state = condvar
+            .wait_while(state.lock().unwrap(), |state| state.events.is_empty())
+            .unwrap();
+
+        match state.events.pop_front().unwrap() {
+            Event::Selection(Some(mut data)) => policy
+                .select(data.keys().cloned())?
+                .into_iter()
+                .map(|mime_type| {
+                    let content = data.remove(&mime_type).unwrap();
+                    let pipe = pipe_from(content).map_err(Error::PipeCreation)?;
+                    Ok((mime_type, pipe))
+                })
+                .collect(),
+            Event::Selection(None) => Err(Error::ClipboardEmpty),
+            Event::Disconnect => Err(Error::WaylandConnection(ConnectError::NoCompositor)),
+        }
+    }
+
+    fn selection(&self) -> Option<SelectionHandle> {
+        Some(SelectionHandle::new(self.clone()))
+    }
+}
+
+impl SetSelection for MockBackend {
+    fn set_selection(&self, data: MimeDataMap, _primary: bool) -> Result<(), Error> {
+        // like a compositor, notify the watchers of our own selection
+        self.push(Event::Selection(Some(data)));
+        Ok(())
+    }
+}
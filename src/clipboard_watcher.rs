@@ -1,18 +1,29 @@
 #![allow(dead_code)]
 use std::{
     collections::{HashMap, HashSet},
-    io::{self, PipeReader},
-    os::fd::AsFd,
+    fs::File,
+    io::{self, PipeReader, Write},
+    os::fd::{AsFd, BorrowedFd},
+    sync::Arc,
 };
 
 use cosmic::cctk::{
-    sctk::reexports::protocols_wlr::data_control::v1::client::{
-        zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
-        zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
-        zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    sctk::reexports::{
+        protocols::ext::data_control::v1::client::{
+            ext_data_control_device_v1::{self, ExtDataControlDeviceV1},
+            ext_data_control_manager_v1::ExtDataControlManagerV1,
+            ext_data_control_offer_v1::{self, ExtDataControlOfferV1},
+            ext_data_control_source_v1::{self, ExtDataControlSourceV1},
+        },
+        protocols_wlr::data_control::v1::client::{
+            zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+            zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+            zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+            zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+        },
     },
     wayland_client::{
-        self, ConnectError, Connection, Dispatch, DispatchError, EventQueue, Proxy,
+        self, ConnectError, Connection, Dispatch, DispatchError, EventQueue, Proxy, QueueHandle,
         delegate_dispatch, event_created_child,
         globals::{BindError, GlobalError, GlobalListContents, registry_queue_init},
         protocol::{
@@ -22,6 +33,11 @@ use cosmic::cctk::{
     },
 };
 
+use crate::{clipboard::ReadPolicy, db::MimeDataMap};
+
+#[cfg(test)]
+mod test;
+
 /// Seat to operate on.
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, PartialOrd, Ord, Default)]
 pub enum Seat<'a> {
@@ -34,19 +50,193 @@ pub enum Seat<'a> {
     Specific(&'a str),
 }
 
+/// The data control manager, from the standard ext protocol when the compositor supports it,
+/// else from the wlr one. Both protocols have the same requests and events.
+#[derive(Clone)]
+pub enum DataControlManager {
+    Ext(ExtDataControlManagerV1),
+    Wlr(ZwlrDataControlManagerV1),
+}
+
+impl DataControlManager {
+    pub fn get_data_device<S>(&self, seat: &WlSeat, qh: &QueueHandle<S>) -> DataControlDevice
+    where
+        S: Dispatch<ExtDataControlDeviceV1, WlSeat> + 'static,
+        S: Dispatch<ZwlrDataControlDeviceV1, WlSeat>,
+    {
+        match self {
+            DataControlManager::Ext(manager) => {
+                DataControlDevice::Ext(manager.get_data_device(seat, qh, seat.clone()))
+            }
+            DataControlManager::Wlr(manager) => {
+                DataControlDevice::Wlr(manager.get_data_device(seat, qh, seat.clone()))
+            }
+        }
+    }
+
+    /// Create a source offering `data`.
+    pub fn create_data_source<S>(
+        &self,
+        qh: &QueueHandle<S>,
+        data: Arc<MimeDataMap>,
+    ) -> DataControlSource
+    where
+        S: Dispatch<ExtDataControlSourceV1, Arc<MimeDataMap>> + 'static,
+        S: Dispatch<ZwlrDataControlSourceV1, Arc<MimeDataMap>>,
+    {
+        let source = match self {
+            DataControlManager::Ext(manager) => {
+                DataControlSource::Ext(manager.create_data_source(qh, data.clone()))
+            }
+            DataControlManager::Wlr(manager) => {
+                DataControlSource::Wlr(manager.create_data_source(qh, data.clone()))
+            }
+        };
+
+        for mime_type in data.keys() {
+            source.offer(mime_type.clone());
+        }
+
+        source
+    }
+
+    pub fn interface_name(&self) -> &'static str {
+        match self {
+            DataControlManager::Ext(_) => ExtDataControlManagerV1::interface().name,
+            DataControlManager::Wlr(_) => ZwlrDataControlManagerV1::interface().name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DataControlDevice {
+    Ext(ExtDataControlDeviceV1),
+    Wlr(ZwlrDataControlDeviceV1),
+}
+
+impl DataControlDevice {
+    pub fn set_selection(&self, source: Option<&DataControlSource>) {
+        match (self, source) {
+            (DataControlDevice::Ext(device), Some(DataControlSource::Ext(source))) => {
+                device.set_selection(Some(source))
+            }
+            (DataControlDevice::Ext(device), None) => device.set_selection(None),
+            (DataControlDevice::Wlr(device), Some(DataControlSource::Wlr(source))) => {
+                device.set_selection(Some(source))
+            }
+            (DataControlDevice::Wlr(device), None) => device.set_selection(None),
+            _ => unreachable!("sources and devices come from the same manager"),
+        }
+    }
+
+    pub fn supports_primary_selection(&self) -> bool {
+        match self {
+            DataControlDevice::Ext(_) => true,
+            DataControlDevice::Wlr(device) => device.version() >= 2,
+        }
+    }
+
+    pub fn set_primary_selection(&self, source: Option<&DataControlSource>) {
+        match (self, source) {
+            (DataControlDevice::Ext(device), Some(DataControlSource::Ext(source))) => {
+                device.set_primary_selection(Some(source))
+            }
+            (DataControlDevice::Ext(device), None) => device.set_primary_selection(None),
+            (DataControlDevice::Wlr(device), Some(DataControlSource::Wlr(source))) => {
+                device.set_primary_selection(Some(source))
+            }
+            (DataControlDevice::Wlr(device), None) => device.set_primary_selection(None),
+            _ => unreachable!("sources and devices come from the same manager"),
+        }
+    }
+
+    pub fn destroy(&self) {
+        match self {
+            DataControlDevice::Ext(device) => device.destroy(),
+            DataControlDevice::Wlr(device) => device.destroy(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DataControlOffer {
+    Ext(ExtDataControlOfferV1),
+    Wlr(ZwlrDataControlOfferV1),
+}
+
+impl DataControlOffer {
+    pub fn receive(&self, mime_type: String, fd: BorrowedFd<'_>) {
+        match self {
+            DataControlOffer::Ext(offer) => offer.receive(mime_type, fd),
+            DataControlOffer::Wlr(offer) => offer.receive(mime_type, fd),
+        }
+    }
+
+    pub fn destroy(&self) {
+        match self {
+            DataControlOffer::Ext(offer) => offer.destroy(),
+            DataControlOffer::Wlr(offer) => offer.destroy(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DataControlSource {
+    Ext(ExtDataControlSourceV1),
+    Wlr(ZwlrDataControlSourceV1),
+}
+
+impl DataControlSource {
+    pub fn offer(&self, mime_type: String) {
+        match self {
+            DataControlSource::Ext(source) => source.offer(mime_type),
+            DataControlSource::Wlr(source) => source.offer(mime_type),
+        }
+    }
+
+    pub fn destroy(&self) {
+        match self {
+            DataControlSource::Ext(source) => source.destroy(),
+            DataControlSource::Wlr(source) => source.destroy(),
+        }
+    }
+}
+
+/// Write the content of `mime_type` for a client pasting it.
+fn send_source_data(data: &Arc<MimeDataMap>, mime_type: String, fd: std::os::fd::OwnedFd) {
+    let data = data.clone();
+
+    // don't block the dispatch, the client reads at its pace
+    std::thread::spawn(move || {
+        if let Some(content) = data.get(&mime_type)
+            && let Err(e) = File::from(fd).write_all(content)
+        {
+            warn!("can't send {mime_type}: {e}");
+        }
+    });
+}
+
+/// Events of a data control device, common to both protocols.
+enum DeviceEvent {
+    DataOffer(DataControlOffer),
+    Selection(Option<DataControlOffer>),
+    Finished,
+    PrimarySelection(Option<DataControlOffer>),
+}
+
 #[derive(Default)]
 pub struct SeatData {
     /// The name of this seat, if any.
     pub name: Option<String>,
 
     /// The data device of this seat, if any.
-    pub device: Option<ZwlrDataControlDeviceV1>,
+    pub device: Option<DataControlDevice>,
 
     /// The data offer of this seat, if any.
-    pub offer: Option<ZwlrDataControlOfferV1>,
+    pub offer: Option<DataControlOffer>,
 
     /// The primary-selection data offer of this seat, if any.
-    pub primary_offer: Option<ZwlrDataControlOfferV1>,
+    pub primary_offer: Option<DataControlOffer>,
 }
 
 impl SeatData {
@@ -58,7 +248,7 @@ impl SeatData {
     /// Sets this seat's device.
     ///
     /// Destroys the old one, if any.
-    pub fn set_device(&mut self, device: Option<ZwlrDataControlDeviceV1>) {
+    pub fn set_device(&mut self, device: Option<DataControlDevice>) {
         let old_device = self.device.take();
         self.device = device;
 
@@ -70,7 +260,7 @@ impl SeatData {
     /// Sets this seat's data offer.
     ///
     /// Destroys the old one, if any.
-    pub fn set_offer(&mut self, new_offer: Option<ZwlrDataControlOfferV1>) {
+    pub fn set_offer(&mut self, new_offer: Option<DataControlOffer>) {
         let old_offer = self.offer.take();
         self.offer = new_offer;
 
@@ -82,7 +272,7 @@ impl SeatData {
     /// Sets this seat's primary-selection data offer.
     ///
     /// Destroys the old one, if any.
-    pub fn set_primary_offer(&mut self, new_offer: Option<ZwlrDataControlOfferV1>) {
+    pub fn set_primary_offer(&mut self, new_offer: Option<DataControlOffer>) {
         let old_offer = self.primary_offer.take();
         self.primary_offer = new_offer;
 
@@ -93,13 +283,13 @@ impl SeatData {
 }
 
 pub struct Event {
-    pub event: ZwlrDataControlOfferV1,
+    pub event: DataControlOffer,
     pub data: HashSet<String>,
 }
 
 pub struct CommonState {
     pub seats: Vec<(WlSeat, SeatData)>,
-    pub clipboard_manager: ZwlrDataControlManagerV1,
+    pub clipboard_manager: DataControlManager,
 }
 
 impl CommonState {
@@ -135,10 +325,12 @@ struct State {
     common: CommonState,
     // The value is the set of MIME types in the offer.
     // TODO: We never remove offers from here, even if we don't use them or after destroying them.
-    offers: HashMap<ZwlrDataControlOfferV1, HashSet<String>>,
+    offers: HashMap<DataControlOffer, HashSet<String>>,
     got_primary_selection: bool,
     // waker: Waker,
     events: Vec<Event>,
+    /// Sources replaced by another selection.
+    cancelled_sources: usize,
 }
 
 delegate_dispatch!(State: [WlSeat: ()] => CommonState);
@@ -161,6 +353,50 @@ impl Dispatch<WlRegistry, GlobalListContents> for State {
     }
 }
 
+impl State {
+    fn device_event(&mut self, seat: &WlSeat, event: DeviceEvent) {
+        match event {
+            DeviceEvent::DataOffer(offer) => {
+                self.offers.insert(offer.clone(), HashSet::new());
+                self.events.push(Event {
+                    event: offer,
+                    data: HashSet::new(),
+                })
+            }
+            DeviceEvent::Selection(offer) => {
+                self.common.get_mut_seat(seat).unwrap().set_offer(offer);
+            }
+            DeviceEvent::Finished => {
+                // Destroy the device stored in the seat as it's no longer valid.
+                self.common.get_mut_seat(seat).unwrap().set_device(None);
+            }
+            DeviceEvent::PrimarySelection(offer) => {
+                self.got_primary_selection = true;
+                self.common
+                    .get_mut_seat(seat)
+                    .unwrap()
+                    .set_primary_offer(offer);
+            }
+        }
+    }
+
+    fn offer_event(&mut self, offer: DataControlOffer, mime_type: String) {
+        self.offers.get_mut(&offer).unwrap().insert(mime_type);
+    }
+}
+
+impl Dispatch<ExtDataControlManagerV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ExtDataControlManagerV1,
+        _event: <ExtDataControlManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
 impl Dispatch<ZwlrDataControlManagerV1, ()> for State {
     fn event(
         _state: &mut Self,
@@ -173,6 +409,37 @@ impl Dispatch<ZwlrDataControlManagerV1, ()> for State {
     }
 }
 
+impl Dispatch<ExtDataControlDeviceV1, WlSeat> for State {
+    fn event(
+        state: &mut Self,
+        _device: &ExtDataControlDeviceV1,
+        event: <ExtDataControlDeviceV1 as wayland_client::Proxy>::Event,
+        seat: &WlSeat,
+        _conn: &wayland_client::Connection,
+        _qh: &wayland_client::QueueHandle<Self>,
+    ) {
+        let event = match event {
+            ext_data_control_device_v1::Event::DataOffer { id } => {
+                DeviceEvent::DataOffer(DataControlOffer::Ext(id))
+            }
+            ext_data_control_device_v1::Event::Selection { id } => {
+                DeviceEvent::Selection(id.map(DataControlOffer::Ext))
+            }
+            ext_data_control_device_v1::Event::Finished => DeviceEvent::Finished,
+            ext_data_control_device_v1::Event::PrimarySelection { id } => {
+                DeviceEvent::PrimarySelection(id.map(DataControlOffer::Ext))
+            }
+            _ => return,
+        };
+
+        state.device_event(seat, event);
+    }
+
+    event_created_child!(State, ExtDataControlDeviceV1, [
+        ext_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ExtDataControlOfferV1, ()),
+    ]);
+}
+
 impl Dispatch<ZwlrDataControlDeviceV1, WlSeat> for State {
     fn event(
         state: &mut Self,
@@ -182,31 +449,21 @@ impl Dispatch<ZwlrDataControlDeviceV1, WlSeat> for State {
         _conn: &wayland_client::Connection,
         _qh: &wayland_client::QueueHandle<Self>,
     ) {
-        match event {
+        let event = match event {
             zwlr_data_control_device_v1::Event::DataOffer { id } => {
-                state.offers.insert(id.clone(), HashSet::new());
-                state.events.push(Event {
-                    event: id,
-                    data: HashSet::new(),
-                })
+                DeviceEvent::DataOffer(DataControlOffer::Wlr(id))
             }
             zwlr_data_control_device_v1::Event::Selection { id } => {
-                state.common.get_mut_seat(seat).unwrap().set_offer(id);
-            }
-            zwlr_data_control_device_v1::Event::Finished => {
-                // Destroy the device stored in the seat as it's no longer valid.
-                state.common.get_mut_seat(seat).unwrap().set_device(None);
+                DeviceEvent::Selection(id.map(DataControlOffer::Wlr))
             }
+            zwlr_data_control_device_v1::Event::Finished => DeviceEvent::Finished,
             zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
-                state.got_primary_selection = true;
-                state
-                    .common
-                    .get_mut_seat(seat)
-                    .unwrap()
-                    .set_primary_offer(id);
+                DeviceEvent::PrimarySelection(id.map(DataControlOffer::Wlr))
             }
-            _ => (),
-        }
+            _ => return,
+        };
+
+        state.device_event(seat, event);
     }
 
     event_created_child!(State, ZwlrDataControlDeviceV1, [
@@ -214,6 +471,21 @@ impl Dispatch<ZwlrDataControlDeviceV1, WlSeat> for State {
     ]);
 }
 
+impl Dispatch<ExtDataControlOfferV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        offer: &ExtDataControlOfferV1,
+        event: <ExtDataControlOfferV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        if let ext_data_control_offer_v1::Event::Offer { mime_type } = event {
+            state.offer_event(DataControlOffer::Ext(offer.clone()), mime_type);
+        }
+    }
+}
+
 impl Dispatch<ZwlrDataControlOfferV1, ()> for State {
     fn event(
         state: &mut Self,
@@ -224,7 +496,53 @@ impl Dispatch<ZwlrDataControlOfferV1, ()> for State {
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
-            state.offers.get_mut(offer).unwrap().insert(mime_type);
+            state.offer_event(DataControlOffer::Wlr(offer.clone()), mime_type);
+        }
+    }
+}
+
+impl Dispatch<ExtDataControlSourceV1, Arc<MimeDataMap>> for State {
+    fn event(
+        state: &mut Self,
+        source: &ExtDataControlSourceV1,
+        event: <ExtDataControlSourceV1 as wayland_client::Proxy>::Event,
+        data: &Arc<MimeDataMap>,
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            ext_data_control_source_v1::Event::Send { mime_type, fd } => {
+                send_source_data(data, mime_type, fd);
+            }
+            // Replaced by another selection.
+            ext_data_control_source_v1::Event::Cancelled => {
+                state.cancelled_sources += 1;
+                source.destroy();
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, Arc<MimeDataMap>> for State {
+    fn event(
+        state: &mut Self,
+        source: &ZwlrDataControlSourceV1,
+        event: <ZwlrDataControlSourceV1 as wayland_client::Proxy>::Event,
+        data: &Arc<MimeDataMap>,
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                send_source_data(data, mime_type, fd);
+            }
+            // Replaced by another selection.
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                state.cancelled_sources += 1;
+                source.destroy();
+            }
+            _ => (),
         }
     }
 }
@@ -250,6 +568,9 @@ pub enum Error {
     #[error("The clipboard of the requested seat is empty")]
     ClipboardEmpty,
 
+    #[error("None of the offered mime types is allowed")]
+    Filtered,
+
     #[error("The compositor does not support primary selection")]
     PrimarySelectionUnsupported,
 
@@ -258,27 +579,72 @@ pub enum Error {
 
     #[error("Couldn't create a pipe for content transfer")]
     PipeCreation(#[source] io::Error),
+
+    #[error("Couldn't connect to the X server")]
+    X11Connection(#[source] x11rb::errors::ConnectError),
+
+    #[error("X server communication error")]
+    X11Communication(#[source] x11rb::errors::ReplyOrIdError),
 }
 
 pub struct Watcher {
     state: State,
     queue: EventQueue<State>,
     primary: bool,
+    conn: Connection,
+}
+
+/// Sets the selection from any thread. The events of the sources are dispatched
+/// by the [`Watcher`], so it must be watching.
+#[derive(Clone)]
+pub struct SelectionSetter {
+    conn: Connection,
+    qh: QueueHandle<State>,
+    manager: DataControlManager,
+    devices: Vec<DataControlDevice>,
+}
+
+impl SelectionSetter {
+    pub fn set_selection(&self, data: MimeDataMap, primary: bool) -> Result<(), Error> {
+        let data = Arc::new(data);
+
+        if primary
+            && !self
+                .devices
+                .iter()
+                .all(DataControlDevice::supports_primary_selection)
+        {
+            return Err(Error::PrimarySelectionUnsupported);
+        }
+
+        for device in &self.devices {
+            // a source can only be used once
+            let source = self.manager.create_data_source(&self.qh, data.clone());
+
+            if primary {
+                device.set_primary_selection(Some(&source));
+            } else {
+                device.set_selection(Some(&source));
+            }
+        }
+
+        self.conn
+            .flush()
+            .map_err(|e| Error::WaylandCommunication(e.into()))
+    }
 }
 
-pub fn initialize<S>() -> Result<(EventQueue<S>, CommonState), Error>
+pub fn initialize<S>(conn: &Connection) -> Result<(EventQueue<S>, CommonState), Error>
 where
     S: Dispatch<WlRegistry, GlobalListContents> + 'static,
+    S: Dispatch<ExtDataControlManagerV1, ()>,
     S: Dispatch<ZwlrDataControlManagerV1, ()>,
     S: Dispatch<WlSeat, ()>,
     S: AsMut<CommonState>,
 {
-    // Connect to the Wayland compositor.
-    let conn = Connection::connect_to_env().map_err(Error::WaylandConnection)?;
-
     // Retrieve the global interfaces.
     let (globals, queue) =
-        registry_queue_init::<S>(&conn).map_err(|err| match err {
+        registry_queue_init::<S>(conn).map_err(|err| match err {
                                            GlobalError::Backend(err) => Error::WaylandCommunication(err.into()),
                                            GlobalError::InvalidId(err) => panic!("How's this possible? \
                                                                                   Is there no wl_registry? \
@@ -287,17 +653,25 @@ where
                                        })?;
     let qh = &queue.handle();
 
-    // Verify that we got the clipboard manager.
+    // Verify that we got the clipboard manager, preferring the standard protocol.
     let clipboard_manager = match globals.bind(qh, 1..=1, ()) {
-        Ok(manager) => manager,
+        Ok(manager) => DataControlManager::Ext(manager),
         Err(BindError::NotPresent | BindError::UnsupportedVersion) => {
-            return Err(Error::MissingProtocol {
-                name: ZwlrDataControlManagerV1::interface().name,
-                version: 1,
-            });
+            // primary selection needs version 2
+            match globals.bind(qh, 1..=2, ()) {
+                Ok(manager) => DataControlManager::Wlr(manager),
+                Err(BindError::NotPresent | BindError::UnsupportedVersion) => {
+                    return Err(Error::MissingProtocol {
+                        name: ExtDataControlManagerV1::interface().name,
+                        version: 1,
+                    });
+                }
+            }
         }
     };
 
+    debug!("bound {}", clipboard_manager.interface_name());
+
     let registry = globals.registry();
     let seats = globals.contents().with_list(|globals| {
         globals
@@ -320,7 +694,14 @@ where
 
 impl Watcher {
     pub fn init() -> Result<Self, Error> {
-        let (queue, mut common) = initialize::<State>()?;
+        // Connect to the Wayland compositor.
+        let conn = Connection::connect_to_env().map_err(Error::WaylandConnection)?;
+
+        Self::with_connection(&conn)
+    }
+
+    pub fn with_connection(conn: &Connection) -> Result<Self, Error> {
+        let (queue, mut common) = initialize::<State>(conn)?;
 
         // Check if there are no seats.
         if common.seats.is_empty() {
@@ -329,10 +710,9 @@ impl Watcher {
 
         // Go through the seats and get their data devices.
         for (seat, data) in &mut common.seats {
-            let device =
-                common
-                    .clipboard_manager
-                    .get_data_device(seat, &queue.handle(), seat.clone());
+            let device = common
+                .clipboard_manager
+                .get_data_device(seat, &queue.handle());
             data.set_device(Some(device));
         }
 
@@ -341,17 +721,55 @@ impl Watcher {
             offers: HashMap::new(),
             got_primary_selection: false,
             events: Vec::new(),
+            cancelled_sources: 0,
         };
 
         Ok(Watcher {
             state,
             queue,
             primary: false,
+            conn: conn.clone(),
         })
     }
 
+    pub fn selection_setter(&self) -> SelectionSetter {
+        SelectionSetter {
+            conn: self.conn.clone(),
+            qh: self.queue.handle(),
+            manager: self.state.common.clipboard_manager.clone(),
+            devices: self
+                .state
+                .common
+                .seats
+                .iter()
+                .filter_map(|(_, data)| data.device.clone())
+                .collect(),
+        }
+    }
+
+    /// Own the selection until every seat replaced it, answering the requests
+    /// meanwhile. For a process that doesn't watch, like the command line.
+    pub fn serve_selection(&mut self, data: MimeDataMap, primary: bool) -> Result<(), Error> {
+        self.state.cancelled_sources = 0;
+
+        let setter = self.selection_setter();
+        setter.set_selection(data, primary)?;
+
+        while self.state.cancelled_sources < setter.devices.len() {
+            self.queue
+                .blocking_dispatch(&mut self.state)
+                .map_err(Error::WaylandCommunication)?;
+        }
+
+        Ok(())
+    }
+
     // note: returning an iter cause some bugs with pipes
-    pub fn start_watching(&mut self, seat: Seat<'_>) -> Result<Vec<(String, PipeReader)>, Error> {
+    pub fn start_watching(
+        &mut self,
+        seat: Seat<'_>,
+        policy: &ReadPolicy,
+    ) -> Result<Vec<(String, PipeReader)>, Error> {
         self.queue
             .blocking_dispatch(&mut self.state)
             .map_err(Error::WaylandCommunication)?;
@@ -386,7 +804,7 @@ impl Watcher {
         // Check if we found anything.
         match offer.clone() {
             Some(offer) => {
-                let mime_types = self.state.offers.remove(&offer).unwrap();
+                let mime_types = policy.select(self.state.offers.remove(&offer).unwrap())?;
 
                 let mut res = Vec::with_capacity(mime_types.len());
 
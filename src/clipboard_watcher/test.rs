@@ -0,0 +1,423 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::{fd::AsFd, unix::net::UnixStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use cosmic::cctk::wayland_client::Connection;
+use wayland_protocols::ext::data_control::v1::server::{
+    ext_data_control_device_v1::{self, ExtDataControlDeviceV1},
+    ext_data_control_manager_v1::{self, ExtDataControlManagerV1},
+    ext_data_control_offer_v1::{self, ExtDataControlOfferV1},
+    ext_data_control_source_v1::{self, ExtDataControlSourceV1},
+};
+use wayland_protocols_wlr::data_control::v1::server::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::{self, ZwlrDataControlManagerV1},
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New, Resource,
+    backend::ClientData,
+    protocol::wl_seat::{self, WlSeat},
+};
+
+use crate::{clipboard::ReadPolicy, db::MimeDataMap};
+
+use super::{Error, Seat, Watcher};
+
+const CONTENT: &str = "hello";
+
+/// Compositor whose selection is always [`CONTENT`], as `text/plain`.
+/// The selections set by the client are pasted right away.
+struct FakeCompositor {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    /// Primary selection or not, mime type and content.
+    pasted: mpsc::Receiver<(bool, String, Vec<u8>)>,
+}
+
+/// State of the fake compositor.
+struct Compositor {
+    pasted: mpsc::Sender<(bool, String, Vec<u8>)>,
+}
+
+impl Compositor {
+    fn paste(&self, source: Option<Source>, primary: bool) {
+        let Some(source) = source else {
+            return;
+        };
+
+        for mime_type in source.mime_types() {
+            let (mut read, write) = std::io::pipe().unwrap();
+            source.send(mime_type.clone(), write.as_fd());
+
+            let pasted = self.pasted.clone();
+            thread::spawn(move || {
+                let mut content = Vec::new();
+                read.read_to_end(&mut content).unwrap();
+                let _ = pasted.send((primary, mime_type, content));
+            });
+        }
+    }
+}
+
+/// Offered mime types.
+type SourceData = Mutex<Vec<String>>;
+
+enum Source {
+    Ext(ExtDataControlSourceV1),
+    Wlr(ZwlrDataControlSourceV1),
+}
+
+impl Source {
+    fn mime_types(&self) -> Vec<String> {
+        match self {
+            Source::Ext(source) => source.data::<SourceData>(),
+            Source::Wlr(source) => source.data::<SourceData>(),
+        }
+        .unwrap()
+        .lock()
+        .unwrap()
+        .clone()
+    }
+
+    fn send(&self, mime_type: String, fd: std::os::fd::BorrowedFd<'_>) {
+        match self {
+            Source::Ext(source) => source.send(mime_type, fd),
+            Source::Wlr(source) => source.send(mime_type, fd),
+        }
+    }
+}
+
+struct TestClient;
+
+impl ClientData for TestClient {}
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    Ext,
+    Wlr,
+}
+
+impl FakeCompositor {
+    fn start(protocols: &[Protocol]) -> (Self, Connection) {
+        let mut display = Display::<Compositor>::new().unwrap();
+        let dh = display.handle();
+
+        dh.create_global::<Compositor, WlSeat, ()>(2, ());
+
+        for protocol in protocols {
+            match protocol {
+                Protocol::Ext => dh.create_global::<Compositor, ExtDataControlManagerV1, ()>(1, ()),
+                Protocol::Wlr => {
+                    dh.create_global::<Compositor, ZwlrDataControlManagerV1, ()>(2, ())
+                }
+            };
+        }
+
+        let (server, client) = UnixStream::pair().unwrap();
+        display
+            .handle()
+            .insert_client(server, Arc::new(TestClient))
+            .unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (pasted_tx, pasted) = mpsc::channel();
+
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let mut compositor = Compositor { pasted: pasted_tx };
+
+                while !stop.load(Ordering::Relaxed) {
+                    display.dispatch_clients(&mut compositor).unwrap();
+                    display.flush_clients().unwrap();
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        });
+
+        let compositor = FakeCompositor {
+            stop,
+            thread: Some(thread),
+            pasted,
+        };
+
+        (compositor, Connection::from_socket(client).unwrap())
+    }
+}
+
+impl Drop for FakeCompositor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+impl GlobalDispatch<WlSeat, ()> for Compositor {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlSeat>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let seat = data_init.init(resource, ());
+        seat.name("seat0".into());
+    }
+}
+
+impl Dispatch<WlSeat, ()> for Compositor {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlSeat,
+        _request: wl_seat::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+/// Both protocols are the same, with different names.
+macro_rules! data_control {
+    (
+        $manager:ident,
+        $manager_mod:ident,
+        $device:ident,
+        $device_mod:ident,
+        $offer:ident,
+        $offer_mod:ident,
+        $source:ident,
+        $source_mod:ident,
+        $variant:ident
+    ) => {
+        impl GlobalDispatch<$manager, ()> for Compositor {
+            fn bind(
+                _state: &mut Self,
+                _handle: &DisplayHandle,
+                _client: &Client,
+                resource: New<$manager>,
+                _global_data: &(),
+                data_init: &mut DataInit<'_, Self>,
+            ) {
+                data_init.init(resource, ());
+            }
+        }
+
+        impl Dispatch<$manager, ()> for Compositor {
+            fn request(
+                _state: &mut Self,
+                client: &Client,
+                _resource: &$manager,
+                request: $manager_mod::Request,
+                _data: &(),
+                dhandle: &DisplayHandle,
+                data_init: &mut DataInit<'_, Self>,
+            ) {
+                match request {
+                    $manager_mod::Request::GetDataDevice { id, .. } => {
+                        let device = data_init.init(id, ());
+
+                        let offer = client
+                            .create_resource::<$offer, (), Self>(dhandle, device.version(), ())
+                            .unwrap();
+                        device.data_offer(&offer);
+                        offer.offer("text/plain".into());
+                        device.selection(Some(&offer));
+                    }
+                    $manager_mod::Request::CreateDataSource { id } => {
+                        data_init.init(id, SourceData::default());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        impl Dispatch<$device, ()> for Compositor {
+            fn request(
+                state: &mut Self,
+                _client: &Client,
+                _resource: &$device,
+                request: $device_mod::Request,
+                _data: &(),
+                _dhandle: &DisplayHandle,
+                _data_init: &mut DataInit<'_, Self>,
+            ) {
+                match request {
+                    $device_mod::Request::SetSelection { source } => {
+                        state.paste(source.map(Source::$variant), false);
+                    }
+                    $device_mod::Request::SetPrimarySelection { source } => {
+                        state.paste(source.map(Source::$variant), true);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        impl Dispatch<$source, SourceData> for Compositor {
+            fn request(
+                _state: &mut Self,
+                _client: &Client,
+                _resource: &$source,
+                request: $source_mod::Request,
+                data: &SourceData,
+                _dhandle: &DisplayHandle,
+                _data_init: &mut DataInit<'_, Self>,
+            ) {
+                if let $source_mod::Request::Offer { mime_type } = request {
+                    data.lock().unwrap().push(mime_type);
+                }
+            }
+        }
+
+        impl Dispatch<$offer, ()> for Compositor {
+            fn request(
+                _state: &mut Self,
+                _client: &Client,
+                _resource: &$offer,
+                request: $offer_mod::Request,
+                _data: &(),
+                _dhandle: &DisplayHandle,
+                _data_init: &mut DataInit<'_, Self>,
+            ) {
+                if let $offer_mod::Request::Receive { fd, .. } = request {
+                    File::from(fd).write_all(CONTENT.as_bytes()).unwrap();
+                }
+            }
+        }
+    };
+}
+
+data_control!(
+    ExtDataControlManagerV1,
+    ext_data_control_manager_v1,
+    ExtDataControlDeviceV1,
+    ext_data_control_device_v1,
+    ExtDataControlOfferV1,
+    ext_data_control_offer_v1,
+    ExtDataControlSourceV1,
+    ext_data_control_source_v1,
+    Ext
+);
+
+data_control!(
+    ZwlrDataControlManagerV1,
+    zwlr_data_control_manager_v1,
+    ZwlrDataControlDeviceV1,
+    zwlr_data_control_device_v1,
+    ZwlrDataControlOfferV1,
+    zwlr_data_control_offer_v1,
+    ZwlrDataControlSourceV1,
+    zwlr_data_control_source_v1,
+    Wlr
+);
+
+/// Return the protocol used by the watcher, and the content of the selection.
+fn watch(protocols: &[Protocol]) -> Result<(&'static str, String), Error> {
+    let (_compositor, conn) = FakeCompositor::start(protocols);
+
+    let mut watcher = Watcher::with_connection(&conn)?;
+
+    let offer = watcher.start_watching(Seat::Unspecified, &ReadPolicy::default())?;
+    assert_eq!(offer.len(), 1);
+
+    // sent with the next dispatch otherwise
+    conn.flush().unwrap();
+
+    let (mime_type, mut pipe) = offer.into_iter().next().unwrap();
+    assert_eq!(mime_type, "text/plain");
+
+    let mut content = String::new();
+    pipe.read_to_string(&mut content).unwrap();
+
+    Ok((
+        watcher.state.common.clipboard_manager.interface_name(),
+        content,
+    ))
+}
+
+#[test]
+fn prefer_ext_protocol() {
+    let (protocol, content) = watch(&[Protocol::Wlr, Protocol::Ext]).unwrap();
+
+    assert_eq!(protocol, "ext_data_control_manager_v1");
+    assert_eq!(content, CONTENT);
+}
+
+#[test]
+fn fallback_to_wlr_protocol() {
+    let (protocol, content) = watch(&[Protocol::Wlr]).unwrap();
+
+    assert_eq!(protocol, "zwlr_data_control_manager_v1");
+    assert_eq!(content, CONTENT);
+}
+
+#[test]
+fn missing_protocol() {
+    let error = watch(&[]).unwrap_err();
+
+    assert!(matches!(
+        error,
+        Error::MissingProtocol {
+            name: "ext_data_control_manager_v1",
+            version: 1
+        }
+    ));
+}
+
+fn set_selection(protocol: Protocol) {
+    let (compositor, conn) = FakeCompositor::start(&[protocol]);
+
+    let mut watcher = Watcher::with_connection(&conn).unwrap();
+    let setter = watcher.selection_setter();
+
+    let data = MimeDataMap::from([("text/plain".into(), b"copied".to_vec())]);
+    setter.set_selection(data.clone(), false).unwrap();
+    setter.set_selection(data, true).unwrap();
+
+    // the watcher dispatches the requests for the content
+    watcher.queue.roundtrip(&mut watcher.state).unwrap();
+
+    let mut pasted = (0..2)
+        .map(|_| {
+            compositor
+                .pasted
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    pasted.sort();
+
+    assert_eq!(
+        pasted,
+        vec![
+            (false, "text/plain".into(), b"copied".to_vec()),
+            (true, "text/plain".into(), b"copied".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn set_selection_ext() {
+    set_selection(Protocol::Ext);
+}
+
+#[test]
+fn set_selection_wlr() {
+    set_selection(Protocol::Wlr);
+}
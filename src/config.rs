@@ -16,7 +16,12 @@ use cosmic::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::{app::APPID, message::AppMsg};
+use crate::{
+    app::APPID,
+    db::{CopyPolicy, Normalization, SearchMode, SortOrder},
+    message::AppMsg,
+    utils,
+};
 
 pub const CONFIG_VERSION: u64 = 3;
 
@@ -35,11 +40,76 @@ pub struct Config {
     pub unique_session: bool,
     pub maximum_entries_by_page: NonZeroU32,
     pub preferred_mime_types: Vec<String>,
+    /// Rank search results by fuzzy score instead of substring matching by recency
+    pub fuzzy_search: bool,
+    /// Extract the text of images with tesseract, to search them
+    pub text_recognition: bool,
+    /// Offer the last copied entry again when the application it comes from exits
+    pub keep_clipboard_alive: bool,
+    /// Don't save the mime types of a copy bigger than this, in MiB
+    pub maximum_mime_type_size: Option<u64>,
+    /// Regexes of the mime types to save, all of them when empty
+    pub allowed_mime_types: Vec<String>,
+    /// Regexes of the mime types to never save
+    pub ignored_mime_types: Vec<String>,
+    /// Consider the texts only differing by whitespaces as duplicates
+    pub duplicates_ignore_whitespace: bool,
+    /// Consider the texts only differing by case as duplicates
+    pub duplicates_ignore_case: bool,
+    /// List the most copied entries first
+    pub sort_by_usage: bool,
+    /// Don't move the entries copied from the history to the top
+    pub keep_copied_position: bool,
+    /// Between the texts of the entries copied together, with `\n` and `\t` escapes
+    pub concatenation_separator: String,
+    /// Show the number of entries captured since the popup was last opened
+    pub new_entries_badge: bool,
+    /// Notify when the clipboard can't be watched
+    pub notify_errors: bool,
+    /// Notify when an offer is not recorded, none of its mime types being allowed
+    pub notify_filtered: bool,
+    /// Notify when an entry is copied from the history
+    pub notify_copied: bool,
 }
 
 pub static PRIVATE_MODE: AtomicBool = AtomicBool::new(false);
 
 impl Config {
+    pub fn search_mode(&self) -> SearchMode {
+        if self.fuzzy_search {
+            SearchMode::Fuzzy
+        } else {
+            SearchMode::Substring
+        }
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        if self.sort_by_usage {
+            SortOrder::MostUsed
+        } else {
+            SortOrder::Recent
+        }
+    }
+
+    pub fn copy_policy(&self) -> CopyPolicy {
+        if self.keep_copied_position {
+            CopyPolicy::KeepPosition
+        } else {
+            CopyPolicy::BumpToTop
+        }
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        Normalization {
+            whitespace: self.duplicates_ignore_whitespace,
+            ignore_case: self.duplicates_ignore_case,
+        }
+    }
+
+    pub fn concatenation_separator(&self) -> String {
+        utils::unescape(&self.concatenation_separator)
+    }
+
     pub fn maximum_entries_lifetime(&self) -> Option<Duration> {
         self.maximum_entries_lifetime
             .map(|s| Duration::from_secs(s * 24 * 60 * 60))
@@ -56,6 +126,21 @@ impl Default for Config {
             unique_session: false,
             maximum_entries_by_page: NonZero::new(50).unwrap(),
             preferred_mime_types: Vec::new(),
+            fuzzy_search: false,
+            text_recognition: false,
+            keep_clipboard_alive: true,
+            maximum_mime_type_size: Some(100),
+            allowed_mime_types: Vec::new(),
+            ignored_mime_types: Vec::new(),
+            duplicates_ignore_whitespace: false,
+            duplicates_ignore_case: false,
+            sort_by_usage: false,
+            keep_copied_position: false,
+            concatenation_separator: "\\n".into(),
+            new_entries_badge: false,
+            notify_errors: false,
+            notify_filtered: false,
+            notify_copied: false,
         }
     }
 }
@@ -1,8 +1,9 @@
-use std::{collections::HashMap, fmt::Debug, path::Path, sync::LazyLock};
+use std::{collections::HashMap, fmt::Debug, path::Path, sync::LazyLock, time::Duration};
 
 use anyhow::Result;
 
 use chrono::Utc;
+use futures::{Stream, StreamExt};
 use regex::Regex;
 
 use crate::config::Config;
@@ -10,8 +11,11 @@ use crate::config::Config;
 #[cfg(test)]
 pub mod test;
 
+mod search;
+pub use search::{SearchMatch, SearchMode, SearchResults};
+
 mod sqlite_db;
-pub use sqlite_db::DbSqlite;
+pub use sqlite_db::{DbSqlite, EntryHash, entry_hash};
 
 fn now() -> i64 {
     Utc::now().timestamp_millis()
@@ -63,6 +67,72 @@ impl Debug for Content<'_> {
     }
 }
 
+/// How the texts are normalized, to detect near-duplicates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Normalization {
+    /// Trim and collapse the whitespaces.
+    pub whitespace: bool,
+    pub ignore_case: bool,
+}
+
+impl Normalization {
+    pub fn is_enabled(&self) -> bool {
+        self.whitespace || self.ignore_case
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        let text = if self.whitespace {
+            text.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            text.to_owned()
+        };
+
+        if self.ignore_case {
+            text.to_lowercase()
+        } else {
+            text
+        }
+    }
+}
+
+/// Order of the entries which are not favorites.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Recent,
+    /// By copy count, then by recency.
+    MostUsed,
+}
+
+/// What happens to an entry copied from the history.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CopyPolicy {
+    /// Move it to the top, like a new copy.
+    #[default]
+    BumpToTop,
+    KeepPosition,
+}
+
+/// Entries to clear, relative to now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearRange {
+    /// Copied during the last duration.
+    Last(Duration),
+    OlderThan(Duration),
+}
+
+impl ClearRange {
+    /// Bounds for [`DbTrait::delete_range`].
+    pub fn bounds(&self, now: i64) -> (i64, i64) {
+        let millis = |duration: &Duration| duration.as_millis().try_into().unwrap_or(i64::MAX);
+
+        match self {
+            ClearRange::Last(duration) => (now.saturating_sub(millis(duration)), i64::MAX),
+            ClearRange::OlderThan(duration) => (i64::MIN, now.saturating_sub(millis(duration))),
+        }
+    }
+}
+
 /// More we have mime types here, Less we spend time in the [`EntryTrait::preferred_content`] function.
 const PRIV_MIME_TYPES_SIMPLE: &[&str] = &[
     "image/png",
@@ -75,6 +145,18 @@ const PRIV_MIME_TYPES_SIMPLE: &[&str] = &[
     "UTF8_STRING",
     "TEXT",
 ];
+/// Mime types of the plain texts, by preference.
+const PLAIN_TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+];
+
+/// Pseudo mime type of the text recognized in an image.
+const RECOGNIZED_TEXT_MIME: &str = "text/x-recognized";
+
 const PRIV_MIME_TYPES_REGEX_STR: &[&str] = &["text/plain*", "text/*", "image/*"];
 
 static PRIV_MIME_TYPES_REGEX: LazyLock<Vec<Regex>> = LazyLock::new(|| {
@@ -94,6 +176,45 @@ pub trait EntryTrait: Debug + Clone + Send {
 
     fn id(&self) -> EntryId;
 
+    /// Millis
+    fn creation(&self) -> i64;
+
+    /// Text recognized in the image, empty if nothing was found.
+    fn recognized_text(&self) -> Option<&str>;
+
+    /// Number of copies from the history.
+    fn copy_count(&self) -> u32;
+
+    /// Millis of the last copy from the history.
+    fn last_used(&self) -> Option<i64>;
+
+    /// Higher for the entries copied often and recently.
+    fn frecency(&self, now: i64) -> u64 {
+        const DAY: i64 = 24 * 60 * 60 * 1000;
+
+        let age = (now - self.last_used().unwrap_or(self.creation())) / DAY;
+
+        let weight = if age < 4 {
+            100
+        } else if age < 14 {
+            70
+        } else if age < 31 {
+            50
+        } else if age < 90 {
+            30
+        } else {
+            10
+        };
+
+        (u64::from(self.copy_count()) + 1) * weight
+    }
+
+    /// Rank of a ranked search result: its score, boosted by up to about 75%
+    /// for the entries copied often and recently.
+    fn search_rank(&self, score: u16, now: i64) -> u64 {
+        u64::from(score) * (16 + u64::from(self.frecency(now).max(1).ilog2()))
+    }
+
     // note: hot fn, do not log
     fn preferred_content(
         &self,
@@ -140,22 +261,36 @@ pub trait EntryTrait: Debug + Clone + Send {
         None
     }
 
-    fn searchable_content(&self) -> impl Iterator<Item = &str> {
-        self.raw_content().iter().filter_map(|(mime, content)| {
-            if mime.starts_with("text/") {
-                let text = core::str::from_utf8(content).ok()?;
+    fn plain_text(&self) -> Option<&str> {
+        PLAIN_TEXT_MIME_TYPES.iter().find_map(|mime| {
+            let content = self.raw_content().get(*mime)?;
+            core::str::from_utf8(content).ok()
+        })
+    }
 
-                if mime == "text/html"
-                    && let Some(alt) = find_alt(text)
-                {
-                    return Some(alt);
-                }
+    /// (Mime, Text, Offset of the text in the content)
+    fn searchable_content(&self) -> impl Iterator<Item = (&str, &str, usize)> {
+        self.raw_content()
+            .iter()
+            .filter_map(|(mime, content)| {
+                if mime.starts_with("text/") {
+                    let text = core::str::from_utf8(content).ok()?;
+
+                    if mime == "text/html"
+                        && let Some((offset, alt)) = find_alt(text)
+                    {
+                        return Some((mime.as_str(), alt, offset));
+                    }
 
-                return Some(text);
-            }
+                    return Some((mime.as_str(), text, 0));
+                }
 
-            None
-        })
+                None
+            })
+            .chain(
+                self.recognized_text()
+                    .map(|text| (RECOGNIZED_TEXT_MIME, text, 0)),
+            )
     }
 }
 
@@ -168,28 +303,96 @@ pub trait DbTrait: Sized {
 
     async fn reload(&mut self) -> Result<()>;
 
-    async fn insert(&mut self, data: MimeDataMap) -> Result<()>;
+    /// Return the id of the new or bumped entry, [`None`] if another instance owns the db.
+    async fn insert(&mut self, data: MimeDataMap) -> Result<Option<EntryId>>;
 
-    async fn insert_with_time(&mut self, data: MimeDataMap, time: i64) -> Result<()>;
+    async fn insert_with_time(&mut self, data: MimeDataMap, time: i64) -> Result<Option<EntryId>>;
 
+    /// Move the entry to the trash, see [`DbTrait::undo_delete`].
+    /// A favorite is restored at its position.
     async fn delete(&mut self, data: EntryId) -> Result<()>;
 
-    async fn clear(&mut self) -> Result<()>;
+    /// Move the entries to the trash at once, so they are restored together.
+    async fn delete_many(&mut self, entries: &[EntryId]) -> Result<()>;
+
+    /// Delete the entries, except the favorites.
+    /// They are moved to the trash when `trash` is true,
+    /// otherwise the trash is emptied too.
+    async fn clear(&mut self, trash: bool) -> Result<()>;
+
+    /// Delete the entries created in `from..to`, except the favorites.
+    /// They are moved to the trash when `trash` is true.
+    /// Return the number of deleted entries.
+    async fn delete_range(&mut self, from: i64, to: i64, trash: bool) -> Result<usize>;
+
+    /// Restore the entries of the last deletion, unless the trash was purged.
+    /// Return the number of restored entries.
+    async fn undo_delete(&mut self) -> Result<usize>;
 
     async fn add_favorite(&mut self, entry: EntryId, index: Option<usize>) -> Result<()>;
 
     async fn remove_favorite(&mut self, entry: EntryId) -> Result<()>;
 
-    fn search(&mut self);
+    async fn set_recognized_text(&mut self, entry: EntryId, text: String) -> Result<()>;
+
+    /// Texts equal after the normalization replace each other on insertion,
+    /// except the favorites, which are bumped.
+    fn set_normalization(&mut self, normalization: Normalization);
+
+    /// Count a copy of the entry from the history, and move it according to `policy`.
+    async fn mark_used(&mut self, entry: EntryId, policy: CopyPolicy) -> Result<()>;
+
+    fn set_sort_order(&mut self, order: SortOrder);
+
+    /// Only keep the favorites and the most recent entry of each group of duplicates.
+    /// The others are moved to the trash at once.
+    /// Return the number of deleted entries.
+    async fn merge_duplicates(&mut self) -> Result<usize>;
 
-    fn set_query_and_search(&mut self, query: String);
+    /// Only parse the query, see [`DbTrait::start_search`].
+    fn set_query(&mut self, query: String);
+
+    /// Start searching the current query, in the background.
+    /// The results must be given to [`DbTrait::apply_search_results`].
+    /// Cancel the previous search.
+    fn start_search(
+        &mut self,
+    ) -> Option<impl Stream<Item = SearchResults> + Send + 'static + use<Self>>;
+
+    /// Results of a cancelled search are ignored.
+    fn apply_search_results(&mut self, results: SearchResults);
+
+    /// Search until completion.
+    async fn search(&mut self) {
+        if let Some(stream) = self.start_search() {
+            let results = stream.collect::<Vec<_>>().await;
+
+            for results in results {
+                self.apply_search_results(results);
+            }
+        }
+    }
+
+    async fn set_query_and_search(&mut self, query: String) {
+        self.set_query(query);
+        self.search().await;
+    }
+
+    /// Mode used when the query has no mode prefix.
+    fn set_search_mode(&mut self, mode: SearchMode);
 
     fn get_query(&self) -> &str;
 
+    /// Where the current query matched this entry.
+    fn get_search_match(&self, id: EntryId) -> Option<&SearchMatch>;
+
     fn get(&self, index: usize) -> Option<&Self::Entry>;
 
     fn get_from_id(&self, id: EntryId) -> Option<&Self::Entry>;
 
+    /// Most recent entry, favorite or not.
+    fn last(&self) -> Option<&Self::Entry>;
+
     fn iter(&self) -> impl Iterator<Item = &'_ Self::Entry>;
 
     fn search_iter(&self) -> impl Iterator<Item = &'_ Self::Entry>;
@@ -203,7 +406,8 @@ pub trait DbTrait: Sized {
 
     fn len(&self) -> usize;
 
-    async fn handle_message(&mut self, message: DbMessage) -> Result<()>;
+    /// Return true if the entries were reloaded, after a change by another instance.
+    async fn handle_message(&mut self, message: DbMessage) -> Result<bool>;
 
     fn is_search_active(&self) -> bool {
         !self.get_query().is_empty()
@@ -214,8 +418,33 @@ pub trait DbTrait: Sized {
 pub enum DbMessage {
     CheckUpdate,
 }
+/// Join the plain texts of the entries, skipping the others.
+pub fn concatenate<'a, E: EntryTrait + 'a>(
+    entries: impl IntoIterator<Item = &'a E>,
+    separator: &str,
+) -> Option<MimeDataMap> {
+    let texts = entries
+        .into_iter()
+        .filter_map(EntryTrait::plain_text)
+        .collect::<Vec<_>>();
+
+    if texts.is_empty() {
+        return None;
+    }
+
+    let text = texts.join(separator);
+
+    Some(
+        ["text/plain;charset=utf-8", "text/plain"]
+            .into_iter()
+            .map(|mime| (mime.to_string(), text.as_bytes().to_vec()))
+            .collect(),
+    )
+}
+
 // currently best effort
-fn find_alt(html: &str) -> Option<&str> {
-    let alt = html.split_once("alt=\"")?.1.split_once('"')?.0;
-    Some(alt)
+fn find_alt(html: &str) -> Option<(usize, &str)> {
+    let (before, after) = html.split_once("alt=\"")?;
+    let alt = after.split_once('"')?.0;
+    Some((before.len() + "alt=\"".len(), alt))
 }
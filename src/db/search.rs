@@ -0,0 +1,500 @@
+use std::{
+    ops::Range,
+    sync::{
+        Arc,
+        atomic::{self, AtomicU64},
+    },
+};
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, Weekday};
+use futures::{Stream, channel::mpsc};
+use nucleo::{
+    Matcher, Utf32Str,
+    pattern::{Atom, AtomKind, CaseMatching, Normalization},
+};
+use regex::{Regex, RegexBuilder};
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{Content, EntryId, EntryTrait, Mime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchMode {
+    /// Ranked by the nucleo score, not by recency
+    Fuzzy,
+    #[default]
+    Substring,
+    Prefix,
+    Regex,
+}
+
+impl SearchMode {
+    /// Split the mode prefix from the query, if any.
+    ///
+    /// - `~foo`: fuzzy
+    /// - `'foo`: exact substring
+    /// - `^foo`: prefix
+    /// - `/foo`: regex
+    pub fn parse(query: &str, default: SearchMode) -> (SearchMode, &str) {
+        let mut chars = query.chars();
+
+        let mode = match chars.next() {
+            Some('~') => SearchMode::Fuzzy,
+            Some('\'') => SearchMode::Substring,
+            Some('^') => SearchMode::Prefix,
+            Some('/') => SearchMode::Regex,
+            _ => return (default, query),
+        };
+
+        (mode, chars.as_str())
+    }
+}
+
+/// A parsed search query: `key:value` filters, the rest being the needle.
+///
+/// - `type:text|image|uri|other`
+/// - `mime:text/html`: mime types starting with this value
+/// - `fav:yes|no`
+/// - `after:2026-10-01`, `before:yesterday`: also `today` and week days
+/// - `size:>1MB`: `>`, `>=`, `<` and `<=`, in B, KB, MB or GB
+pub struct SearchQuery {
+    pub filters: Vec<Filter>,
+    pub needle: Option<Needle>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str, default_mode: SearchMode, now: DateTime<Local>) -> Self {
+        let mut filters = Vec::new();
+        let mut needle = Vec::new();
+
+        // the text between the filters is kept as is, for the exact and regex modes
+        let mut start = 0;
+        let mut offset = 0;
+
+        for piece in query.split_inclusive(char::is_whitespace) {
+            let token = piece.trim_end();
+
+            if let Some(filter) = Filter::parse(token, now) {
+                filters.push(filter);
+                needle.push(query[start..offset].trim());
+                start = offset + token.len();
+            }
+
+            offset += piece.len();
+        }
+
+        needle.push(query[start..].trim());
+        needle.retain(|part| !part.is_empty());
+
+        let needle = needle.join(" ");
+
+        SearchQuery {
+            filters,
+            needle: (!needle.is_empty()).then(|| Needle::new(&needle, default_mode)),
+        }
+    }
+
+    // note: hot fn, do not log
+    pub fn filter<E: EntryTrait>(&self, entry: &E) -> bool {
+        self.filters.iter().all(|f| f.matches(entry))
+    }
+
+    /// The best score of the entry and where it matched, if it matches this query.
+    // note: hot fn, do not log
+    pub fn matches<E: EntryTrait>(
+        &self,
+        entry: &E,
+        matcher: &mut Matcher,
+    ) -> Option<(u16, Option<SearchMatch>)> {
+        if !self.filter(entry) {
+            return None;
+        }
+
+        let Some(needle) = &self.needle else {
+            return Some((0, None));
+        };
+
+        entry
+            .searchable_content()
+            .filter_map(|(mime, text, offset)| {
+                let (score, ranges) = needle.matches(text, matcher)?;
+
+                let search_match = SearchMatch {
+                    mime: mime.to_string(),
+                    ranges: ranges
+                        .into_iter()
+                        .map(|r| r.start + offset..r.end + offset)
+                        .collect(),
+                };
+                Some((score, search_match))
+            })
+            .max_by_key(|(score, _)| *score)
+            .map(|(score, search_match)| (score, Some(search_match)))
+    }
+
+    /// Results are ranked by score instead of recency.
+    pub fn is_ranked(&self) -> bool {
+        self.needle
+            .as_ref()
+            .is_some_and(|needle| needle.mode() == SearchMode::Fuzzy)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    Text,
+    Image,
+    Uri,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Type(EntryType),
+    Mime(String),
+    Favorite(bool),
+    /// Millis, inclusive
+    After(i64),
+    /// Millis, exclusive
+    Before(i64),
+    SizeGreater(usize),
+    SizeLess(usize),
+}
+
+impl Filter {
+    fn parse(token: &str, now: DateTime<Local>) -> Option<Self> {
+        let (key, value) = token.split_once(':')?;
+        let value_lower = value.to_lowercase();
+
+        let filter = match key.to_lowercase().as_str() {
+            "type" => Filter::Type(match value_lower.as_str() {
+                "text" => EntryType::Text,
+                "image" | "img" => EntryType::Image,
+                "uri" | "file" => EntryType::Uri,
+                "other" => EntryType::Other,
+                _ => return None,
+            }),
+            "mime" if !value.is_empty() => Filter::Mime(value_lower),
+            "fav" => Filter::Favorite(match value_lower.as_str() {
+                "yes" | "true" => true,
+                "no" | "false" => false,
+                _ => return None,
+            }),
+            "after" => Filter::After(parse_day(&value_lower, now)?),
+            "before" => Filter::Before(parse_day(&value_lower, now)?),
+            "size" => {
+                if let Some(size) = value.strip_prefix(">=") {
+                    Filter::SizeGreater(parse_size(size)?.saturating_sub(1))
+                } else if let Some(size) = value.strip_prefix("<=") {
+                    Filter::SizeLess(parse_size(size)?.saturating_add(1))
+                } else if let Some(size) = value.strip_prefix('<') {
+                    Filter::SizeLess(parse_size(size)?)
+                } else {
+                    Filter::SizeGreater(parse_size(value.strip_prefix('>').unwrap_or(value))?)
+                }
+            }
+            _ => return None,
+        };
+
+        Some(filter)
+    }
+
+    fn matches<E: EntryTrait>(&self, entry: &E) -> bool {
+        match self {
+            Filter::Type(entry_type) => {
+                let actual = match entry.preferred_content(&[]) {
+                    Some((_, Content::Text(_))) => EntryType::Text,
+                    Some((_, Content::Image(_))) => EntryType::Image,
+                    Some((_, Content::UriList(_))) => EntryType::Uri,
+                    None => EntryType::Other,
+                };
+                actual == *entry_type
+            }
+            Filter::Mime(mime) => entry
+                .raw_content()
+                .keys()
+                .any(|m| m.to_lowercase().starts_with(mime)),
+            Filter::Favorite(favorite) => entry.is_favorite() == *favorite,
+            Filter::After(time) => entry.creation() >= *time,
+            Filter::Before(time) => entry.creation() < *time,
+            Filter::SizeGreater(size) => entry_size(entry) > *size,
+            Filter::SizeLess(size) => entry_size(entry) < *size,
+        }
+    }
+}
+
+fn entry_size<E: EntryTrait>(entry: &E) -> usize {
+    entry.raw_content().values().map(Vec::len).sum()
+}
+
+/// Start of the day, in millis.
+fn parse_day(value: &str, now: DateTime<Local>) -> Option<i64> {
+    let today = now.date_naive();
+
+    let day = match value {
+        "today" => today,
+        "yesterday" => today.checked_sub_days(Days::new(1))?,
+        _ => match value.parse::<Weekday>() {
+            // the last one, today included
+            Ok(weekday) => {
+                let delta = (7 + today.weekday().num_days_from_monday()
+                    - weekday.num_days_from_monday())
+                    % 7;
+                today.checked_sub_days(Days::new(delta.into()))?
+            }
+            Err(_) => NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?,
+        },
+    };
+
+    let time = day
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()?
+        .timestamp_millis();
+
+    Some(time)
+}
+
+fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim().to_lowercase();
+
+    let pos = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(pos);
+
+    let number: f64 = number.parse().ok()?;
+
+    let unit: f64 = match unit {
+        "" | "b" => 1.,
+        "k" | "kb" | "kib" => 1024.,
+        "m" | "mb" | "mib" => 1024. * 1024.,
+        "g" | "gb" | "gib" => 1024. * 1024. * 1024.,
+        _ => return None,
+    };
+
+    Some((number * unit) as usize)
+}
+
+pub enum Needle {
+    Atom(SearchMode, Atom),
+    Regex(Regex),
+}
+
+/// Trigrams need at least 3 chars.
+const FTS_MIN_CHARS: usize = 3;
+
+impl Needle {
+    pub fn new(query: &str, default_mode: SearchMode) -> Self {
+        let (mode, needle) = SearchMode::parse(query, default_mode);
+
+        let kind = match mode {
+            SearchMode::Fuzzy => AtomKind::Fuzzy,
+            SearchMode::Substring => AtomKind::Substring,
+            SearchMode::Prefix => AtomKind::Prefix,
+            SearchMode::Regex => {
+                let smart_case = !needle.chars().any(char::is_uppercase);
+
+                // fall back to a literal match while the user is still typing the pattern
+                let regex = RegexBuilder::new(needle)
+                    .case_insensitive(smart_case)
+                    .build()
+                    .or_else(|_| {
+                        RegexBuilder::new(&regex::escape(needle))
+                            .case_insensitive(smart_case)
+                            .build()
+                    })
+                    .expect("escaped regex should be valid");
+
+                return Needle::Regex(regex);
+            }
+        };
+
+        let atom = Atom::new(
+            needle,
+            CaseMatching::Smart,
+            Normalization::Smart,
+            kind,
+            true,
+        );
+
+        Needle::Atom(mode, atom)
+    }
+
+    pub fn mode(&self) -> SearchMode {
+        match self {
+            Needle::Atom(mode, _) => *mode,
+            Needle::Regex(_) => SearchMode::Regex,
+        }
+    }
+
+    /// A FTS5 query matching a superset of what this needle match, if possible.
+    pub fn fts_query(&self) -> Option<String> {
+        match self {
+            Needle::Atom(SearchMode::Substring | SearchMode::Prefix, atom) => {
+                let text = atom.needle_text().chars().collect::<String>();
+
+                if text.chars().count() < FTS_MIN_CHARS {
+                    return None;
+                }
+
+                Some(format!("\"{}\"", text.replace('"', "\"\"")))
+            }
+            _ => None,
+        }
+    }
+
+    /// The score, and the byte ranges of `text` matched by this needle, sorted and merged.
+    // note: hot fn, do not log
+    pub fn matches(&self, text: &str, matcher: &mut Matcher) -> Option<(u16, Vec<Range<usize>>)> {
+        match self {
+            Needle::Atom(_, atom) => {
+                let mut buf = Vec::new();
+                let mut indices = Vec::new();
+
+                let haystack = Utf32Str::new(text, &mut buf);
+                let is_ascii = matches!(haystack, Utf32Str::Ascii(_));
+
+                let score = atom.indices(haystack, matcher, &mut indices)?;
+
+                indices.sort_unstable();
+                indices.dedup();
+
+                let ranges = if is_ascii {
+                    bytes_to_ranges(text, &indices)
+                } else {
+                    graphemes_to_ranges(text, &indices)
+                };
+
+                Some((score, ranges))
+            }
+            Needle::Regex(regex) => {
+                let ranges = merge_ranges(
+                    regex
+                        .find_iter(text)
+                        .filter(|m| !m.is_empty())
+                        .map(|m| m.range()),
+                );
+
+                (!ranges.is_empty()).then_some((0, ranges))
+            }
+        }
+    }
+}
+
+/// Where the query matched an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub mime: Mime,
+    /// Byte ranges in the content of `mime`
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// A chunk of the results of a search.
+#[derive(Debug, Clone)]
+pub struct SearchResults {
+    pub generation: u64,
+    /// The previous results must be discarded
+    pub first: bool,
+    /// (Id, Score, Match), in the order of the entries
+    pub results: Vec<(EntryId, u16, Option<SearchMatch>)>,
+}
+
+/// Entries matched before sending them back.
+const CHUNK_SIZE: usize = 512;
+
+/// Match the entries on another thread, sending the results by chunks.
+/// Stop as soon as `generation` is not the current one anymore.
+pub fn search_in_background<E: EntryTrait + 'static>(
+    query: Arc<SearchQuery>,
+    entries: Vec<E>,
+    generation: u64,
+    current_generation: Arc<AtomicU64>,
+) -> impl Stream<Item = SearchResults> {
+    let (tx, rx) = mpsc::unbounded();
+
+    std::thread::spawn(move || {
+        let mut matcher = Matcher::new(nucleo::Config::DEFAULT);
+
+        // send at least one chunk, so the previous results are discarded
+        let mut chunks = entries.chunks(CHUNK_SIZE);
+        let mut chunk = chunks.next().unwrap_or_default();
+        let mut first = true;
+
+        loop {
+            let mut results = Vec::new();
+
+            for entry in chunk {
+                if current_generation.load(atomic::Ordering::Relaxed) != generation {
+                    return;
+                }
+
+                if let Some((score, search_match)) = query.matches(entry, &mut matcher) {
+                    results.push((entry.id(), score, search_match));
+                }
+            }
+
+            let results = SearchResults {
+                generation,
+                first,
+                results,
+            };
+
+            if tx.unbounded_send(results).is_err() {
+                return;
+            }
+
+            first = false;
+
+            match chunks.next() {
+                Some(next) => chunk = next,
+                None => return,
+            }
+        }
+    });
+
+    rx
+}
+
+/// Nucleo indices are byte positions when the haystack was treated as ascii.
+fn bytes_to_ranges(text: &str, indices: &[u32]) -> Vec<Range<usize>> {
+    merge_ranges(
+        indices
+            .iter()
+            .map(|i| *i as usize)
+            .filter(|i| text.is_char_boundary(*i))
+            .filter_map(|i| {
+                let len = text[i..].chars().next()?.len_utf8();
+                Some(i..i + len)
+            }),
+    )
+}
+
+/// Nucleo indices are grapheme positions otherwise.
+fn graphemes_to_ranges(text: &str, indices: &[u32]) -> Vec<Range<usize>> {
+    let mut indices = indices.iter().peekable();
+
+    merge_ranges(
+        text.grapheme_indices(true)
+            .enumerate()
+            .filter_map(|(pos, (start, grapheme))| {
+                while indices.next_if(|i| (**i as usize) < pos).is_some() {}
+
+                indices
+                    .next_if(|i| **i as usize == pos)
+                    .map(|_| start..start + grapheme.len())
+            }),
+    )
+}
+
+fn merge_ranges(ranges: impl Iterator<Item = Range<usize>>) -> Vec<Range<usize>> {
+    let mut res: Vec<Range<usize>> = Vec::new();
+
+    for range in ranges {
+        match res.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => res.push(range),
+        }
+    }
+
+    res
+}
@@ -1,19 +1,18 @@
 use fslock::LockFile;
-use futures::StreamExt;
+use futures::{Stream, StreamExt, lock::Mutex};
+use itertools::Itertools;
 use sqlx::{Sqlite, SqliteConnection, migrate::MigrateDatabase, prelude::*};
 use std::{
-    cell::RefCell,
     collections::{BTreeMap, HashMap, HashSet},
     fmt::Debug,
-    hash::{DefaultHasher, Hash, Hasher},
     path::Path,
+    sync::{
+        Arc,
+        atomic::{self, AtomicU64},
+    },
 };
 
 use anyhow::{Result, anyhow};
-use nucleo::{
-    Matcher, Utf32Str,
-    pattern::{Atom, AtomKind, CaseMatching, Normalization},
-};
 
 use crate::{
     app::{APP, APPID, ORG, QUALIFIER},
@@ -21,29 +20,79 @@ use crate::{
     utils::{self},
 };
 
-use super::{DbMessage, DbTrait, EntryId, EntryTrait, MimeDataMap, PRIV_MIME_TYPES_SIMPLE, now};
+use super::{
+    CopyPolicy, DbMessage, DbTrait, EntryId, EntryTrait, MimeDataMap, Normalization,
+    PLAIN_TEXT_MIME_TYPES, PRIV_MIME_TYPES_SIMPLE, SearchMatch, SearchMode, SearchResults,
+    SortOrder, now,
+    search::{Needle, SearchQuery, search_in_background},
+};
 
 type Time = i64;
+/// Blake3 of the content of an entry, persisted.
+pub type EntryHash = [u8; 32];
 
 const DB_VERSION: &str = "7";
-const DB_FILENAME: &str = constcat::concat!(APPID, "-db-", DB_VERSION, ".sqlite");
+pub(super) const DB_FILENAME: &str = constcat::concat!(APPID, "-db-", DB_VERSION, ".sqlite");
 
 const LOCK_FILENAME: &str = constcat::concat!(APPID, "-db", ".lock");
 
+/// How long the deleted entries can be restored, in ms.
+const TRASH_GRACE_PERIOD: Time = 60 * 1000;
+
+/// Entries or blobs hashed at once, when migrating from a version without their hash.
+pub(super) const HASH_BATCH: i64 = 100;
+
+/// Below this many entries, scanning them all is faster than querying the FTS index.
+const FTS_MIN_ENTRIES: usize = 2000;
+
+/// The FTS prefilter is dropped when more than 1 / this of the entries are candidates:
+/// fetching them costs more than the scan it saves.
+const FTS_SELECTIVITY: usize = 32;
+
+/// Entries moved to the trash at once.
+#[derive(Clone, Copy)]
+struct TrashBatch {
+    id: i64,
+    time: Time,
+}
+
 pub struct DbSqlite {
     conn: SqliteConnection,
     /// Hash -> Id
-    hashs: HashMap<u64, EntryId>,
+    hashs: HashMap<EntryHash, EntryId>,
+    /// Normalized hash -> Id of the most recent entry
+    normalized_hashs: HashMap<EntryHash, EntryId>,
+    normalization: Normalization,
     /// time -> Id
     times: BTreeMap<Time, EntryId>,
+    /// (copy count, time) -> Id
+    usage: BTreeMap<(u32, Time), EntryId>,
+    sort_order: SortOrder,
     /// Id -> Entry
     entries: HashMap<EntryId, Entry>,
-    filtered: Vec<EntryId>,
+    /// (Id, Score)
+    filtered: Vec<(EntryId, u16)>,
+    search_matches: HashMap<EntryId, SearchMatch>,
     query: String,
-    needle: Option<Atom>,
-    matcher: RefCell<Matcher>,
+    search_query: Option<Arc<SearchQuery>>,
+    search_mode: SearchMode,
+    /// Incremented to cancel the running search
+    search_generation: Arc<AtomicU64>,
+    /// Used by the background searches
+    search_conn: Arc<Mutex<SqliteConnection>>,
+    /// Forced on or off by the tests, instead of depending on the size of the history
+    #[cfg(test)]
+    pub(super) fts_prefilter: Option<bool>,
     data_version: i64,
     pub(super) favorites: Favorites,
+    /// Overridden by the tests
+    pub(super) trash_grace_period: Time,
+    /// Time of the last deletion, until the trash is purged
+    last_trashed: Option<Time>,
+    /// Held by the first instance, which alone captures the offers and does the upkeep
+    /// (purge, limits), so they are not done twice. The actions of the user write
+    /// whatever the lock, like the command line while the applet runs: the other
+    /// instances reload on the change of `data_version`.
     lock: LockFile,
 }
 
@@ -52,9 +101,13 @@ pub struct Entry {
     pub id: EntryId,
     pub creation: Time,
     // todo: lazelly load image in memory, since we can't search them anyways?
-    /// (Mime, Content)
-    pub raw_content: MimeDataMap,
+    /// (Mime, Content), shared with the background searches
+    pub raw_content: Arc<MimeDataMap>,
     pub is_favorite: bool,
+    pub recognized_text: Option<Arc<str>>,
+    pub hash: EntryHash,
+    pub copy_count: u32,
+    pub last_used: Option<Time>,
 }
 
 #[derive(Default)]
@@ -104,34 +157,50 @@ impl Favorites {
     }
 }
 
-fn hash_entry_content<H: Hasher>(data: &MimeDataMap, state: &mut H) {
-    for m in PRIV_MIME_TYPES_SIMPLE {
-        if let Some(content) = data.get(*m)
-            && !content.is_empty()
-        {
-            content.hash(state);
-            return;
-        }
+/// Hash of every content and its mime type. The plain text mime types only count once,
+/// being converted from the same text: an offer bridged from X11 has the same hash.
+pub fn entry_hash(data: &MimeDataMap) -> EntryHash {
+    let mut hasher = blake3::Hasher::new();
+
+    // the length separates the mime types and the contents
+    let mut update = |bytes: &[u8]| {
+        hasher.update(&(bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+
+    if let Some(text) = PLAIN_TEXT_MIME_TYPES
+        .iter()
+        .find_map(|m| data.get(*m).filter(|content| !content.is_empty()))
+    {
+        update(b"text/plain");
+        update(text);
     }
 
-    let mut sorted = data.iter().collect::<Vec<_>>();
-    sorted.sort_by(|(mime1, _), (mime2, _)| mime1.cmp(mime2));
+    let others = data
+        .iter()
+        .filter(|(mime, _)| !PLAIN_TEXT_MIME_TYPES.contains(&mime.as_str()))
+        .sorted_by(|(mime1, _), (mime2, _)| mime1.cmp(mime2));
 
-    for (_, content) in sorted {
-        content.hash(state);
+    for (mime, content) in others {
+        update(mime.as_bytes());
+        update(content);
     }
-}
 
-fn get_hash_entry_content(data: &MimeDataMap) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    hash_entry_content(data, &mut hasher);
-    hasher.finish()
+    *hasher.finalize().as_bytes()
 }
 
-impl Hash for Entry {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        hash_entry_content(&self.raw_content, state);
+/// Hash of the normalized text of the entry, [`None`] if it's not a text.
+fn normalized_hash(data: &MimeDataMap, normalization: Normalization) -> Option<EntryHash> {
+    if !normalization.is_enabled() || data.keys().any(|mime| mime.starts_with("image/")) {
+        return None;
     }
+
+    let text = PRIV_MIME_TYPES_SIMPLE
+        .iter()
+        .filter_map(|m| data.get(*m))
+        .find_map(|content| core::str::from_utf8(content).ok().filter(|t| !t.is_empty()))?;
+
+    Some(*blake3::hash(normalization.normalize(text).as_bytes()).as_bytes())
 }
 
 impl EntryTrait for Entry {
@@ -147,16 +216,24 @@ impl EntryTrait for Entry {
         self.id
     }
 
-    fn into_raw_content(self) -> MimeDataMap {
-        self.raw_content
+    fn creation(&self) -> i64 {
+        self.creation
+    }
+
+    fn recognized_text(&self) -> Option<&str> {
+        self.recognized_text.as_deref()
+    }
+
+    fn copy_count(&self) -> u32 {
+        self.copy_count
     }
-}
 
-impl Entry {
-    fn get_hash(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish()
+    fn last_used(&self) -> Option<i64> {
+        self.last_used
+    }
+
+    fn into_raw_content(self) -> MimeDataMap {
+        Arc::unwrap_or_clone(self.raw_content)
     }
 }
 
@@ -213,9 +290,18 @@ impl DbTrait for DbSqlite {
         .run(&mut conn)
         .await?;
 
+        fill_blob_hashes(&mut conn).await?;
+        fill_entry_hashes(&mut conn).await?;
+
+        let search_conn = SqliteConnection::connect(db_path).await?;
+
         let mut lock = LockFile::open(&db_dir.join(LOCK_FILENAME))?;
         lock.try_lock()?;
 
+        if lock.owns_lock() {
+            purge_trash(&mut conn, now() - TRASH_GRACE_PERIOD).await?;
+        }
+
         if lock.owns_lock()
             && let Some(max_duration) = config.maximum_entries_lifetime()
         {
@@ -244,6 +330,7 @@ impl DbTrait for DbSqlite {
             let query_get_most_older = r#"
                 SELECT creation
                 FROM ClipboardEntries
+                WHERE deleted IS NULL
                 ORDER BY creation DESC
                 LIMIT 1 OFFSET ?
             "#;
@@ -281,13 +368,24 @@ impl DbTrait for DbSqlite {
             data_version: fetch_data_version(&mut conn).await?,
             conn,
             hashs: HashMap::default(),
+            normalized_hashs: HashMap::default(),
+            normalization: config.normalization(),
             times: BTreeMap::default(),
+            usage: BTreeMap::default(),
+            sort_order: config.sort_order(),
             entries: HashMap::default(),
             filtered: Vec::default(),
+            search_matches: HashMap::default(),
             query: String::default(),
-            needle: None,
-            matcher: Matcher::new(nucleo::Config::DEFAULT).into(),
+            search_query: None,
+            search_mode: config.search_mode(),
+            search_generation: Arc::default(),
+            search_conn: Arc::new(Mutex::new(search_conn)),
+            #[cfg(test)]
+            fts_prefilter: None,
             favorites: Favorites::default(),
+            trash_grace_period: TRASH_GRACE_PERIOD,
+            last_trashed: None,
             lock,
         };
 
@@ -300,6 +398,7 @@ impl DbTrait for DbSqlite {
         self.hashs.clear();
         self.entries.clear();
         self.times.clear();
+        self.usage.clear();
         self.favorites.clear();
 
         // init favorite
@@ -334,8 +433,9 @@ impl DbTrait for DbSqlite {
         // init entries and times
         {
             let query_load_table = r#"
-                SELECT id, creation
+                SELECT id, creation, hash, copy_count, last_used
                 FROM ClipboardEntries
+                WHERE deleted IS NULL
             "#;
 
             let mut stream = sqlx::query(query_load_table).fetch(&mut self.conn);
@@ -345,17 +445,27 @@ impl DbTrait for DbSqlite {
 
                 let id = row.get("id");
                 let creation = row.get("creation");
+                let hash: Vec<u8> = row.get("hash");
+                let copy_count = row.get("copy_count");
 
                 let entry = Entry {
                     id,
                     creation,
-                    raw_content: MimeDataMap::default(),
+                    raw_content: Arc::default(),
                     is_favorite: self.favorites.contains(&id),
+                    recognized_text: None,
+                    hash: hash
+                        .try_into()
+                        .map_err(|_| anyhow!("invalid hash of entry {id}"))?,
+                    copy_count,
+                    last_used: row.get("last_used"),
                 };
 
+                self.hashs.insert(entry.hash, id);
                 self.entries.insert(id, entry);
 
                 self.times.insert(creation, id);
+                self.usage.insert((copy_count, creation), id);
             }
         }
 
@@ -364,8 +474,11 @@ impl DbTrait for DbSqlite {
             // todo: we can probably optimize by sorting with id
 
             let query_load_table = r#"
-                SELECT id, mime, content
+                SELECT ClipboardContents.id, mime, content
                 FROM ClipboardContents
+                JOIN ClipboardBlobs ON ClipboardBlobs.id = ClipboardContents.blob
+                JOIN ClipboardEntries ON ClipboardEntries.id = ClipboardContents.id
+                WHERE deleted IS NULL
             "#;
 
             let mut stream = sqlx::query(query_load_table).fetch(&mut self.conn);
@@ -378,18 +491,39 @@ impl DbTrait for DbSqlite {
                 let content: Vec<u8> = row.get("content");
 
                 let entry = self.entries.get_mut(&id).expect("entry should exist");
-                entry.raw_content.insert(mime, content);
+                Arc::make_mut(&mut entry.raw_content).insert(mime, content);
             }
         }
 
-        // init hashs
+        // init recognized texts
         {
-            for entry in self.entries.values() {
-                self.hashs.insert(entry.get_hash(), entry.id());
+            let query_load_table = r#"
+                SELECT ClipboardRecognizedTexts.id, text
+                FROM ClipboardRecognizedTexts
+                JOIN ClipboardEntries ON ClipboardEntries.id = ClipboardRecognizedTexts.id
+                WHERE deleted IS NULL
+            "#;
+
+            let mut stream = sqlx::query(query_load_table).fetch(&mut self.conn);
+
+            while let Some(res) = stream.next().await {
+                let row = res?;
+
+                let id = row.get("id");
+                let text: String = row.get("text");
+
+                let entry = self.entries.get_mut(&id).expect("entry should exist");
+                entry.recognized_text = Some(text.into());
             }
         }
 
-        self.search();
+        self.rebuild_normalized_hashs();
+
+        // the results are updated by the next search, started by the caller
+        self.filtered
+            .retain(|(id, _)| self.entries.contains_key(id));
+        self.search_matches
+            .retain(|id, _| self.entries.contains_key(id));
 
         Ok(())
     }
@@ -398,121 +532,246 @@ impl DbTrait for DbSqlite {
         self.entries.get(&id)
     }
 
-    async fn insert(&mut self, data: MimeDataMap) -> Result<()> {
+    fn last(&self) -> Option<&Self::Entry> {
+        self.times.values().next_back().map(|id| &self.entries[id])
+    }
+
+    async fn insert(&mut self, data: MimeDataMap) -> Result<Option<EntryId>> {
         self.insert_with_time(data, now()).await
     }
-    async fn insert_with_time(&mut self, data: MimeDataMap, now: i64) -> Result<()> {
+    async fn insert_with_time(&mut self, data: MimeDataMap, now: i64) -> Result<Option<EntryId>> {
         if !self.lock.owns_lock() {
             info!("db already locked");
-            return Ok(());
+            return Ok(None);
         }
 
-        let hash = get_hash_entry_content(&data);
+        let hash = entry_hash(&data);
+        let normalized_hash = normalized_hash(&data, self.normalization);
 
-        if let Some(id) = self.hashs.get(&hash) {
-            let entry = self.entries.get_mut(id).unwrap();
+        let near_duplicate =
+            normalized_hash.and_then(|hash| self.normalized_hashs.get(&hash).copied());
 
-            let old_creation = entry.creation;
-            entry.creation = now;
-            let res = self.times.remove(&old_creation);
-            assert!(res.is_some());
-            self.times.insert(entry.creation, *id);
+        // the content of the favorites is kept
+        let bumped = self
+            .hashs
+            .get(&hash)
+            .copied()
+            .or(near_duplicate.filter(|id| self.favorites.contains(id)));
 
-            let query_update_creation = r#"
-                UPDATE ClipboardEntries
-                SET creation = $1
-                WHERE id = $2;
+        let id = if let Some(id) = bumped {
+            self.bump(id, now).await?;
+
+            id
+        } else {
+            let id = now as EntryId;
+
+            // the id of a trashed entry can be reused, its favorite is deleted with it
+            if self.favorites.contains(&id) && !self.entries.contains_key(&id) {
+                self.remove_favorite(id).await?;
+            }
+
+            // an entry without all its contents must not be seen
+            let mut tx = Connection::begin(&mut self.conn).await?;
+
+            if let Some(id) = near_duplicate {
+                let query_delete = r#"
+                    DELETE FROM ClipboardEntries
+                    WHERE id = $1;
+                "#;
+
+                sqlx::query(query_delete).bind(id).execute(&mut *tx).await?;
+            }
+
+            let query_purge_id = r#"
+                DELETE FROM ClipboardEntries
+                WHERE id = $1 AND deleted IS NOT NULL
             "#;
 
-            sqlx::query(query_update_creation)
-                .bind(now)
+            sqlx::query(query_purge_id)
                 .bind(id)
-                .execute(&mut self.conn)
+                .execute(&mut *tx)
                 .await?;
-        } else {
-            let id = now as EntryId;
 
             let query_insert_new_entry = r#"
-                INSERT INTO ClipboardEntries (id, creation)
-                SELECT $1, $2
+                INSERT INTO ClipboardEntries (id, creation, hash)
+                SELECT $1, $2, $3
             "#;
 
             sqlx::query(query_insert_new_entry)
                 .bind(id)
                 .bind(now)
-                .execute(&mut self.conn)
+                .bind(hash.as_slice())
+                .execute(&mut *tx)
                 .await?;
 
             for (mime, content) in &data {
+                let hash = blob_hash(content);
+
+                let query_insert_blob = r#"
+                    INSERT INTO ClipboardBlobs (hash, content)
+                    VALUES ($1, $2)
+                    ON CONFLICT (hash) DO NOTHING
+                "#;
+
+                sqlx::query(query_insert_blob)
+                    .bind(hash.as_slice())
+                    .bind(content)
+                    .execute(&mut *tx)
+                    .await?;
+
                 let query_insert_content = r#"
-                    INSERT INTO ClipboardContents (id, mime, content)
-                    SELECT $1, $2, $3
+                    INSERT INTO ClipboardContents (id, mime, blob)
+                    SELECT $1, $2, id
+                    FROM ClipboardBlobs
+                    WHERE hash = $3
                 "#;
 
                 sqlx::query(query_insert_content)
                     .bind(id)
                     .bind(mime)
-                    .bind(content)
-                    .execute(&mut self.conn)
+                    .bind(hash.as_slice())
+                    .execute(&mut *tx)
                     .await?;
             }
 
+            tx.commit().await?;
+
+            if let Some(id) = near_duplicate {
+                self.forget(id);
+            }
+
             let entry = Entry {
                 id,
                 creation: now,
-                raw_content: data,
+                raw_content: Arc::new(data),
                 is_favorite: false,
+                recognized_text: None,
+                hash,
+                copy_count: 0,
+                last_used: None,
             };
 
             self.times.insert(entry.creation, id);
+            self.usage.insert((0, entry.creation), id);
             self.hashs.insert(hash, id);
+            if let Some(normalized_hash) = normalized_hash {
+                self.normalized_hashs.insert(normalized_hash, id);
+            }
             self.entries.insert(id, entry);
+
+            id
+        };
+
+        Ok(Some(id))
+    }
+
+    async fn delete(&mut self, id: EntryId) -> Result<()> {
+        let batch = self.new_trash_batch().await?;
+
+        self.delete_entry(id, Some(batch)).await
+    }
+
+    async fn delete_many(&mut self, ids: &[EntryId]) -> Result<()> {
+        let batch = self.new_trash_batch().await?;
+
+        for id in ids {
+            self.delete_entry(*id, Some(batch)).await?;
         }
 
-        self.search();
         Ok(())
     }
 
-    async fn delete(&mut self, id: EntryId) -> Result<()> {
-        let query = r#"
-            DELETE FROM ClipboardEntries
-            WHERE id = ?;
-        "#;
+    async fn clear(&mut self, trash: bool) -> Result<()> {
+        if trash {
+            let batch = self.new_trash_batch().await?;
 
-        sqlx::query(query).bind(id).execute(&mut self.conn).await?;
+            let query_trash = r#"
+                UPDATE ClipboardEntries
+                SET deleted = $1, trash_batch = $2
+                WHERE deleted IS NULL AND id NOT IN(
+                    SELECT id
+                    FROM FavoriteClipboardEntries
+                );
+            "#;
 
-        match self.entries.remove(&id) {
-            Some(entry) => {
-                self.hashs.remove(&entry.get_hash());
-                self.times.remove(&entry.creation);
+            sqlx::query(query_trash)
+                .bind(batch.time)
+                .bind(batch.id)
+                .execute(&mut self.conn)
+                .await?;
+        } else {
+            let query_delete = r#"
+                DELETE FROM ClipboardEntries
+                WHERE deleted IS NULL AND id NOT IN(
+                    SELECT id
+                    FROM FavoriteClipboardEntries
+                );
+            "#;
 
-                if entry.is_favorite() {
-                    self.favorites.remove(&entry.id);
-                }
-            }
-            None => {
-                warn!("no entry to remove")
-            }
+            sqlx::query(query_delete).execute(&mut self.conn).await?;
+
+            // nothing of the session is left to restore
+            purge_trash(&mut self.conn, Time::MAX).await?;
         }
 
-        self.search();
+        self.reload().await?;
+
         Ok(())
     }
 
-    async fn clear(&mut self) -> Result<()> {
-        let query_delete = r#"
-            DELETE FROM ClipboardEntries
-            WHERE id NOT IN(
-                SELECT id
-                FROM FavoriteClipboardEntries
+    async fn delete_range(&mut self, from: Time, to: Time, trash: bool) -> Result<usize> {
+        if from >= to {
+            return Ok(0);
+        }
+
+        let ids = self
+            .times
+            .range(from..to)
+            .map(|(_, id)| *id)
+            .filter(|id| !self.favorites.contains(id))
+            .collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        if trash {
+            self.delete_many(&ids).await?;
+        } else {
+            for id in &ids {
+                self.delete_entry(*id, None).await?;
+            }
+        }
+
+        Ok(ids.len())
+    }
+
+    async fn undo_delete(&mut self) -> Result<usize> {
+        // unless copied again meanwhile
+        let query_restore = r#"
+            UPDATE ClipboardEntries
+            SET deleted = NULL, trash_batch = NULL
+            WHERE trash_batch = (
+                SELECT MAX(trash_batch)
+                FROM ClipboardEntries
+            ) AND deleted > $1 AND NOT EXISTS (
+                SELECT 1
+                FROM ClipboardEntries AS Alive
+                WHERE Alive.deleted IS NULL AND Alive.hash = ClipboardEntries.hash
             );
         "#;
 
-        sqlx::query(query_delete).execute(&mut self.conn).await?;
+        let restored = sqlx::query(query_restore)
+            .bind(now() - self.trash_grace_period)
+            .execute(&mut self.conn)
+            .await?
+            .rows_affected();
 
-        self.reload().await?;
+        if restored > 0 {
+            self.reload().await?;
+        }
 
-        Ok(())
+        Ok(restored as usize)
     }
 
     async fn add_favorite(&mut self, id: EntryId, index: Option<usize>) -> Result<()> {
@@ -586,70 +845,249 @@ impl DbTrait for DbSqlite {
         Ok(())
     }
 
-    fn search(&mut self) {
-        if self.query.is_empty() {
-            self.filtered.clear();
-        } else if let Some(atom) = &self.needle {
-            self.filtered = self
-                .iter()
-                .filter_map(|entry| {
-                    if entry.searchable_content().any(|text| {
-                        let mut buf = Vec::new();
+    async fn set_recognized_text(&mut self, id: EntryId, text: String) -> Result<()> {
+        let query = r#"
+            INSERT OR REPLACE INTO ClipboardRecognizedTexts (id, text)
+            VALUES ($1, $2);
+        "#;
 
-                        let haystack = Utf32Str::new(text, &mut buf);
+        sqlx::query(query)
+            .bind(id)
+            .bind(&text)
+            .execute(&mut self.conn)
+            .await?;
 
-                        let mut indices = Vec::new();
+        if let Some(e) = self.entries.get_mut(&id) {
+            e.recognized_text = Some(text.into());
+        }
 
-                        let _res =
-                            atom.indices(haystack, &mut self.matcher.borrow_mut(), &mut indices);
+        Ok(())
+    }
 
-                        !indices.is_empty()
-                    }) {
-                        Some(entry.id)
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>();
+    fn set_normalization(&mut self, normalization: Normalization) {
+        if self.normalization != normalization {
+            self.normalization = normalization;
+            self.rebuild_normalized_hashs();
+        }
+    }
+
+    async fn mark_used(&mut self, id: EntryId, policy: CopyPolicy) -> Result<()> {
+        let now = now();
+
+        if policy == CopyPolicy::BumpToTop && self.entries.contains_key(&id) {
+            self.bump(id, now).await?;
+        }
+
+        let query = r#"
+            UPDATE ClipboardEntries
+            SET copy_count = copy_count + 1, last_used = $1
+            WHERE id = $2;
+        "#;
+
+        sqlx::query(query)
+            .bind(now)
+            .bind(id)
+            .execute(&mut self.conn)
+            .await?;
+
+        if let Some(e) = self.entries.get_mut(&id) {
+            self.usage.remove(&(e.copy_count, e.creation));
+            e.copy_count += 1;
+            e.last_used = Some(now);
+            self.usage.insert((e.copy_count, e.creation), id);
+        }
+
+        Ok(())
+    }
+
+    fn set_sort_order(&mut self, order: SortOrder) {
+        self.sort_order = order;
+    }
+
+    async fn merge_duplicates(&mut self) -> Result<usize> {
+        let mut kept = HashSet::new();
+
+        // the favorites first, then the most recent, whatever the sort order
+        let favorites = self
+            .favorites
+            .fav()
+            .iter()
+            .filter_map(|id| self.entries.get(id));
+        let others = self
+            .times
+            .values()
+            .rev()
+            .map(|id| &self.entries[id])
+            .filter(|entry| !entry.is_favorite);
+
+        let duplicates = favorites
+            .chain(others)
+            .filter(|entry| {
+                let key =
+                    normalized_hash(&entry.raw_content, self.normalization).unwrap_or(entry.hash);
+                !kept.insert(key) && !entry.is_favorite
+            })
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+
+        if !duplicates.is_empty() {
+            self.delete_many(&duplicates).await?;
         }
+
+        self.rebuild_normalized_hashs();
+
+        Ok(duplicates.len())
     }
 
-    fn set_query_and_search(&mut self, query: String) {
+    fn set_query(&mut self, query: String) {
         if query.is_empty() {
-            self.needle.take();
+            self.search_query.take();
         } else {
-            let atom = Atom::new(
+            self.search_query.replace(Arc::new(SearchQuery::parse(
                 &query,
-                CaseMatching::Smart,
-                Normalization::Smart,
-                AtomKind::Substring,
-                true,
-            );
-
-            self.needle.replace(atom);
+                self.search_mode,
+                chrono::Local::now(),
+            )));
         }
 
         self.query = query;
+    }
+
+    fn start_search(
+        &mut self,
+    ) -> Option<impl Stream<Item = SearchResults> + Send + 'static + use<>> {
+        let generation = self
+            .search_generation
+            .fetch_add(1, atomic::Ordering::Relaxed)
+            + 1;
+
+        let Some(search_query) = self.search_query.clone() else {
+            self.filtered.clear();
+            self.search_matches.clear();
+            return None;
+        };
+
+        let mut entries = self.iter().cloned().collect::<Vec<_>>();
+
+        let prefilter = entries.len() >= FTS_MIN_ENTRIES;
+        let max_candidates = entries.len() / FTS_SELECTIVITY;
+        #[cfg(test)]
+        let (prefilter, max_candidates) = match self.fts_prefilter {
+            Some(prefilter) => (prefilter, entries.len()),
+            None => (prefilter, max_candidates),
+        };
+
+        let fts_query = search_query
+            .needle
+            .as_ref()
+            .and_then(Needle::fts_query)
+            .filter(|_| prefilter);
+
+        let search_conn = self.search_conn.clone();
+        let current_generation = self.search_generation.clone();
+
+        let stream = async move {
+            if let Some(fts_query) = fts_query {
+                let mut search_conn = search_conn.lock().await;
+
+                match fetch_fts_candidates(&mut search_conn, &fts_query, max_candidates).await {
+                    Ok(Some(candidates)) => {
+                        entries.retain(|entry| candidates.contains(&entry.id));
+                    }
+                    Ok(None) => debug!("the FTS prefilter is not selective"),
+                    Err(e) => error!("can't prefilter the search: {e}"),
+                }
+            }
+
+            search_in_background(search_query, entries, generation, current_generation)
+        };
 
-        self.search();
+        Some(futures::stream::once(stream).flatten())
+    }
+
+    fn apply_search_results(&mut self, results: SearchResults) {
+        if results.generation != self.search_generation.load(atomic::Ordering::Relaxed) {
+            return;
+        }
+
+        if results.first {
+            self.filtered.clear();
+            self.search_matches.clear();
+        }
+
+        let start = self.filtered.len();
+
+        for (id, score, search_match) in results.results {
+            // the entry could have been deleted during the search
+            if !self.entries.contains_key(&id) {
+                continue;
+            }
+
+            if let Some(search_match) = search_match {
+                self.search_matches.insert(id, search_match);
+            }
+            self.filtered.push((id, score));
+        }
+
+        // keep the order of the history between equal ranks,
+        // and for the filters which are not ranked
+        if self
+            .search_query
+            .as_ref()
+            .is_some_and(|search_query| search_query.is_ranked())
+        {
+            let now = now();
+            let entries = &self.entries;
+
+            let rank = |(id, score): &(EntryId, u16)| {
+                std::cmp::Reverse(entries[id].search_rank(*score, now))
+            };
+
+            // the chunks come in the order of the history: sort the new one, and merge it
+            // after the previous ones of the same rank
+            let mut chunk = self.filtered.split_off(start);
+            chunk.sort_by_cached_key(rank);
+
+            self.filtered = std::mem::take(&mut self.filtered)
+                .into_iter()
+                .merge_by(chunk, |previous, new| rank(previous) <= rank(new))
+                .collect();
+        }
+    }
+
+    fn set_search_mode(&mut self, mode: SearchMode) {
+        if self.search_mode != mode {
+            self.search_mode = mode;
+            let query = std::mem::take(&mut self.query);
+            self.set_query(query);
+        }
     }
 
     fn get_query(&self) -> &str {
         &self.query
     }
 
+    fn get_search_match(&self, id: EntryId) -> Option<&SearchMatch> {
+        self.search_matches.get(&id)
+    }
+
     fn get(&self, index: usize) -> Option<&Self::Entry> {
         self.iter().nth(index)
     }
 
     fn iter(&self) -> impl Iterator<Item = &'_ Self::Entry> {
+        let others = match self.sort_order {
+            SortOrder::Recent => itertools::Either::Left(self.times.values()),
+            SortOrder::MostUsed => itertools::Either::Right(self.usage.values()),
+        };
+
+        // the trashed favorites keep their position
         self.favorites
             .fav()
             .iter()
-            .map(|id| &self.entries[id])
+            .filter_map(|id| self.entries.get(id))
             .chain(
-                self.times
-                    .values()
+                others
                     .map(|id| &self.entries[id])
                     .filter(|e| !e.is_favorite)
                     .rev(),
@@ -657,7 +1095,7 @@ impl DbTrait for DbSqlite {
     }
 
     fn search_iter(&self) -> impl Iterator<Item = &'_ Self::Entry> {
-        self.filtered.iter().map(|id| &self.entries[id])
+        self.filtered.iter().map(|(id, _)| &self.entries[id])
     }
 
     fn either_iter(
@@ -681,17 +1119,334 @@ impl DbTrait for DbSqlite {
         }
     }
 
-    async fn handle_message(&mut self, _message: DbMessage) -> Result<()> {
+    async fn handle_message(&mut self, _message: DbMessage) -> Result<bool> {
         let data_version = fetch_data_version(&mut self.conn).await?;
 
-        if self.data_version != data_version {
+        let reload = self.data_version != data_version;
+        if reload {
             self.reload().await?;
         }
 
         self.data_version = data_version;
 
+        let expired = now() - self.trash_grace_period;
+
+        if self.last_trashed.is_some_and(|time| time <= expired) {
+            for id in purge_trash(&mut self.conn, expired).await? {
+                self.favorites.remove(&id);
+            }
+            self.last_trashed = None;
+        }
+
+        Ok(reload)
+    }
+}
+
+impl DbSqlite {
+    /// Delete the entry, or move it to the `trashed` batch.
+    /// A trashed favorite keeps its position until purged.
+    async fn delete_entry(&mut self, id: EntryId, trashed: Option<TrashBatch>) -> Result<()> {
+        match trashed {
+            Some(batch) => {
+                let query = r#"
+                    UPDATE ClipboardEntries
+                    SET deleted = $1, trash_batch = $2
+                    WHERE id = $3;
+                "#;
+
+                sqlx::query(query)
+                    .bind(batch.time)
+                    .bind(batch.id)
+                    .bind(id)
+                    .execute(&mut self.conn)
+                    .await?;
+            }
+            None => {
+                if self.favorites.contains(&id) {
+                    self.remove_favorite(id).await?;
+                }
+
+                let query = r#"
+                    DELETE FROM ClipboardEntries
+                    WHERE id = ?;
+                "#;
+
+                sqlx::query(query).bind(id).execute(&mut self.conn).await?;
+            }
+        }
+
+        self.forget(id);
+
+        Ok(())
+    }
+
+    /// Start a deletion, whose entries are restored together.
+    async fn new_trash_batch(&mut self) -> Result<TrashBatch> {
+        let time = now();
+
+        let query = r#"
+            INSERT INTO TrashBatches (time)
+            VALUES ($1)
+            RETURNING id;
+        "#;
+
+        let id = sqlx::query_scalar(query)
+            .bind(time)
+            .fetch_one(&mut self.conn)
+            .await?;
+
+        self.last_trashed = Some(time);
+
+        Ok(TrashBatch { id, time })
+    }
+
+    /// Remove a deleted entry from memory, except from the favorites.
+    fn forget(&mut self, id: EntryId) {
+        match self.entries.remove(&id) {
+            Some(entry) => {
+                self.hashs.remove(&entry.hash);
+                if let Some(normalized_hash) =
+                    normalized_hash(&entry.raw_content, self.normalization)
+                    && self.normalized_hashs.get(&normalized_hash) == Some(&id)
+                {
+                    self.normalized_hashs.remove(&normalized_hash);
+                }
+                self.times.remove(&entry.creation);
+                self.usage.remove(&(entry.copy_count, entry.creation));
+            }
+            None => {
+                warn!("no entry to remove")
+            }
+        }
+
+        self.filtered.retain(|(filtered_id, _)| *filtered_id != id);
+        self.search_matches.remove(&id);
+    }
+
+    async fn bump(&mut self, id: EntryId, now: i64) -> Result<()> {
+        let entry = self.entries.get_mut(&id).unwrap();
+
+        let old_creation = entry.creation;
+        entry.creation = now;
+        let res = self.times.remove(&old_creation);
+        assert!(res.is_some());
+        self.times.insert(entry.creation, id);
+        self.usage.remove(&(entry.copy_count, old_creation));
+        self.usage.insert((entry.copy_count, entry.creation), id);
+
+        let query_update_creation = r#"
+            UPDATE ClipboardEntries
+            SET creation = $1
+            WHERE id = $2;
+        "#;
+
+        sqlx::query(query_update_creation)
+            .bind(now)
+            .bind(id)
+            .execute(&mut self.conn)
+            .await?;
+
         Ok(())
     }
+
+    fn rebuild_normalized_hashs(&mut self) {
+        self.normalized_hashs.clear();
+
+        // the most recent wins
+        for id in self.times.values() {
+            if let Some(normalized_hash) =
+                normalized_hash(&self.entries[id].raw_content, self.normalization)
+            {
+                self.normalized_hashs.insert(normalized_hash, *id);
+            }
+        }
+    }
+}
+
+/// Delete the entries moved to the trash before `expired`, and return them.
+async fn purge_trash(conn: &mut SqliteConnection, expired: Time) -> Result<Vec<EntryId>> {
+    let query_purge = r#"
+        DELETE FROM ClipboardEntries
+        WHERE deleted <= ?
+        RETURNING id;
+    "#;
+
+    let purged: Vec<EntryId> = sqlx::query_scalar(query_purge)
+        .bind(expired)
+        .fetch_all(&mut *conn)
+        .await?;
+
+    let query_purge_batches = r#"
+        DELETE FROM TrashBatches
+        WHERE time <= ?;
+    "#;
+
+    sqlx::query(query_purge_batches)
+        .bind(expired)
+        .execute(&mut *conn)
+        .await?;
+
+    if !purged.is_empty() {
+        compact_favorites(conn).await?;
+    }
+
+    Ok(purged)
+}
+
+/// Fill the holes left by the favorites deleted with their entry.
+async fn compact_favorites(conn: &mut SqliteConnection) -> Result<()> {
+    let query_load_favs = r#"
+        SELECT id
+        FROM FavoriteClipboardEntries
+        ORDER BY position
+    "#;
+
+    let ids: Vec<EntryId> = sqlx::query_scalar(query_load_favs)
+        .fetch_all(&mut *conn)
+        .await?;
+
+    for (position, id) in ids.into_iter().enumerate() {
+        let query_set_position = r#"
+            UPDATE FavoriteClipboardEntries
+            SET position = $1
+            WHERE id = $2
+        "#;
+
+        sqlx::query(query_set_position)
+            .bind(position as i32)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Key of a blob, stable across versions.
+fn blob_hash(content: &[u8]) -> [u8; 32] {
+    *blake3::hash(content).as_bytes()
+}
+
+/// Hash the blobs moved by the migration to content-addressed storage.
+async fn fill_blob_hashes(conn: &mut SqliteConnection) -> Result<()> {
+    let query_load_blobs = r#"
+        SELECT id, content
+        FROM ClipboardBlobs
+        WHERE hash IS NULL
+        LIMIT $1
+    "#;
+
+    // by batches, like the entries
+    loop {
+        let rows = sqlx::query(query_load_blobs)
+            .bind(HASH_BATCH)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        for row in rows {
+            let id: i64 = row.get("id");
+            let content: Vec<u8> = row.get("content");
+
+            let query_set_hash = r#"
+                UPDATE ClipboardBlobs
+                SET hash = $1
+                WHERE id = $2
+            "#;
+
+            sqlx::query(query_set_hash)
+                .bind(blob_hash(&content).as_slice())
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+}
+
+/// Hash the entries inserted before the hash was persisted.
+async fn fill_entry_hashes(conn: &mut SqliteConnection) -> Result<()> {
+    let query_load_contents = r#"
+        SELECT ClipboardEntries.id, mime, content
+        FROM ClipboardEntries
+        LEFT JOIN ClipboardContents ON ClipboardContents.id = ClipboardEntries.id
+        LEFT JOIN ClipboardBlobs ON ClipboardBlobs.id = ClipboardContents.blob
+        WHERE ClipboardEntries.id IN (
+            SELECT id
+            FROM ClipboardEntries
+            WHERE hash IS NULL
+            LIMIT $1
+        )
+    "#;
+
+    // by batches, the contents of a whole history can be big
+    loop {
+        let rows = sqlx::query(query_load_contents)
+            .bind(HASH_BATCH)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut contents: HashMap<EntryId, MimeDataMap> = HashMap::new();
+
+        for row in rows {
+            let data = contents.entry(row.get("id")).or_default();
+
+            if let Some(mime) = row.get("mime") {
+                data.insert(mime, row.get("content"));
+            }
+        }
+
+        for (id, data) in contents {
+            let query_set_hash = r#"
+                UPDATE ClipboardEntries
+                SET hash = $1
+                WHERE id = $2
+            "#;
+
+            sqlx::query(query_set_hash)
+                .bind(entry_hash(&data).as_slice())
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+}
+
+/// Ids of the entries with a text content or a recognized text matching the FTS5 query.
+/// [`None`] if there are more than `max_candidates`.
+async fn fetch_fts_candidates(
+    conn: &mut SqliteConnection,
+    fts_query: &str,
+    max_candidates: usize,
+) -> Result<Option<HashSet<EntryId>>> {
+    let query = r#"
+        SELECT ClipboardContents.id
+        FROM ClipboardContentsFts
+        JOIN ClipboardContents ON ClipboardContents.blob = ClipboardContentsFts.rowid
+        WHERE ClipboardContentsFts MATCH $1
+        UNION ALL
+        SELECT rowid AS id
+        FROM ClipboardRecognizedTextsFts
+        WHERE ClipboardRecognizedTextsFts MATCH $1
+        LIMIT $2
+    "#;
+
+    let candidates = sqlx::query(query)
+        .bind(fts_query)
+        .bind(max_candidates.saturating_add(1) as i64)
+        .fetch_all(conn)
+        .await?
+        .iter()
+        .map(|row| row.get("id"))
+        .collect::<HashSet<_>>();
+
+    Ok((candidates.len() <= max_candidates).then_some(candidates))
 }
 
 /// https://www.sqlite.org/pragma.html#pragma_data_version
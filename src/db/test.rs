@@ -1,21 +1,38 @@
 use std::{
     fs,
     path::{Path, PathBuf},
+    pin::Pin,
     thread::sleep,
     time::Duration,
 };
 
 use serial_test::serial;
+use sqlx::{Connection, Row, SqliteConnection};
 
 use anyhow::Result;
+use chrono::{Local, TimeZone};
+use futures::{Stream, StreamExt};
+use regex::Regex;
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
+    clipboard::{
+        self, ClipboardError, ClipboardHandler, ClipboardMessage, FILTERED_REPORT_INTERVAL,
+        Handled, KeepAlive, mock::MockBackend, own_offer,
+    },
     config::Config,
-    db::{DbSqlite, DbTrait},
+    db::{
+        ClearRange, CopyPolicy, DbMessage, DbSqlite, DbTrait, EntryId, EntryTrait, Normalization,
+        SearchMode, SortOrder,
+    },
+    export, utils,
 };
 
-use super::MimeDataMap;
+use super::{
+    MimeDataMap, concatenate,
+    search::{Filter, SearchQuery},
+    sqlite_db::{DB_FILENAME, HASH_BATCH},
+};
 
 fn prepare_db_dir() -> PathBuf {
     let fmt_layer = fmt::layer().with_target(false);
@@ -43,13 +60,18 @@ async fn test() -> Result<()> {
 
     test_db(&mut db).await.unwrap();
 
-    db.clear().await?;
+    db.clear(true).await?;
 
     test_db(&mut db).await.unwrap();
 
     Ok(())
 }
 
+async fn search_ids(db: &mut DbSqlite, query: &str) -> Vec<EntryId> {
+    db.set_query_and_search(query.into()).await;
+    db.search_iter().map(|e| e.id).collect()
+}
+
 fn build_content(content: &[(&str, &str)]) -> MimeDataMap {
     content
         .iter()
@@ -82,7 +104,7 @@ async fn test_db(db: &mut DbSqlite) -> Result<()> {
 
     let next = db.iter().next().unwrap();
 
-    assert!(next.raw_content == data2);
+    assert!(*next.raw_content == data2);
 
     Ok(())
 }
@@ -169,9 +191,12 @@ async fn favorites() {
 
     db.delete(now3).await.unwrap();
 
-    assert_eq!(db.favorites.len(), 0);
+    // kept in the trash
+    assert_eq!(db.favorites.len(), 1);
 
+    // until its id is reused
     db.insert_with_time(data3.clone(), now3).await.unwrap();
+    assert_eq!(db.favorites.len(), 0);
 
     db.add_favorite(now1, None).await.unwrap();
 
@@ -237,6 +262,241 @@ async fn lock() {
     assert_eq!(db2.len(), 0);
 }
 
+#[tokio::test]
+#[serial]
+async fn search_modes() {
+    let db_path = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_path)
+        .await
+        .unwrap();
+
+    db.insert_with_time(build_content(&[("text/plain", "hello world")]), 1000)
+        .await
+        .unwrap();
+    db.insert_with_time(build_content(&[("text/plain", "world hello")]), 2000)
+        .await
+        .unwrap();
+    db.insert_with_time(build_content(&[("text/plain", "hxexlxlxo")]), 3000)
+        .await
+        .unwrap();
+
+    assert_eq!(search_ids(&mut db, "hello").await, vec![2000, 1000]);
+    assert_eq!(search_ids(&mut db, "'hello").await, vec![2000, 1000]);
+    assert_eq!(search_ids(&mut db, "^hello").await, vec![1000]);
+    assert_eq!(search_ids(&mut db, "/^w.*o$").await, vec![2000]);
+    // invalid regex fall back to a literal match
+    assert_eq!(search_ids(&mut db, "/hello(").await, Vec::<EntryId>::new());
+
+    // fuzzy: best score first, not the most recent
+    assert_eq!(search_ids(&mut db, "~hello").await, vec![2000, 1000, 3000]);
+    db.set_search_mode(SearchMode::Fuzzy);
+    assert_eq!(search_ids(&mut db, "hllo").await, vec![2000, 1000, 3000]);
+
+    db.set_query_and_search("wrld".into()).await;
+    assert_eq!(db.get_search_match(2000).unwrap().ranges, vec![0..1, 2..5]);
+
+    db.insert_with_time(build_content(&[("text/plain", "un été")]), 4000)
+        .await
+        .unwrap();
+    db.set_query_and_search("~été".into()).await;
+    assert_eq!(db.get_search_match(4000).unwrap().ranges, vec![3..8]);
+}
+
+#[tokio::test]
+#[serial]
+async fn search_match_html_alt() {
+    let db_path = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_path)
+        .await
+        .unwrap();
+
+    db.insert_with_time(
+        build_content(&[("text/html", "<img src=\"a.png\" alt=\"a cat\">")]),
+        1000,
+    )
+    .await
+    .unwrap();
+
+    db.set_query_and_search("cat".into()).await;
+
+    let search_match = db.get_search_match(1000).unwrap();
+    assert_eq!(search_match.mime, "text/html");
+    assert_eq!(search_match.ranges, vec![24..27]);
+}
+
+#[tokio::test]
+#[serial]
+async fn search_filters() {
+    let db_path = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_path)
+        .await
+        .unwrap();
+
+    let now = utils::now_millis();
+    let old = now - 3 * 24 * 60 * 60 * 1000;
+
+    db.insert_with_time(build_content(&[("text/plain", "old text")]), old)
+        .await
+        .unwrap();
+    db.insert_with_time(
+        build_content(&[("text/html", "<b>text</b>"), ("text/plain", "text")]),
+        old + 1,
+    )
+    .await
+    .unwrap();
+    db.insert_with_time(
+        build_content(&[("image/png", &"x".repeat(2 * 1024 * 1024))]),
+        now,
+    )
+    .await
+    .unwrap();
+    db.insert_with_time(build_content(&[("text/uri-list", "file:///a")]), now + 1)
+        .await
+        .unwrap();
+
+    db.add_favorite(old, None).await.unwrap();
+
+    assert_eq!(search_ids(&mut db, "type:image").await, vec![now]);
+    assert_eq!(search_ids(&mut db, "type:uri").await, vec![now + 1]);
+    assert_eq!(search_ids(&mut db, "type:text").await, vec![old, old + 1]);
+    assert_eq!(search_ids(&mut db, "mime:text/html").await, vec![old + 1]);
+    assert_eq!(search_ids(&mut db, "fav:yes").await, vec![old]);
+    assert_eq!(search_ids(&mut db, "fav:no text").await, vec![old + 1]);
+    assert_eq!(
+        search_ids(&mut db, "after:yesterday").await,
+        vec![now + 1, now]
+    );
+    assert_eq!(
+        search_ids(&mut db, "before:yesterday").await,
+        vec![old, old + 1]
+    );
+    assert_eq!(search_ids(&mut db, "size:>1MB").await, vec![now]);
+    assert_eq!(
+        search_ids(&mut db, "size:<1kb type:text old").await,
+        vec![old]
+    );
+    // unknown keys are part of the needle
+    assert_eq!(search_ids(&mut db, "file:///a").await, vec![now + 1]);
+}
+
+#[test]
+fn parse_search_query() {
+    let now = Local.with_ymd_and_hms(2026, 10, 15, 12, 0, 0).unwrap(); // a thursday
+    let day = |y, m, d| {
+        Local
+            .with_ymd_and_hms(y, m, d, 0, 0, 0)
+            .unwrap()
+            .timestamp_millis()
+    };
+
+    let query = SearchQuery::parse(
+        "after:tuesday  hello before:2026-10-15 size:>=1kb world mime:",
+        SearchMode::Substring,
+        now,
+    );
+
+    assert_eq!(
+        query.filters,
+        vec![
+            Filter::After(day(2026, 10, 13)),
+            Filter::Before(day(2026, 10, 15)),
+            Filter::SizeGreater(1023),
+        ]
+    );
+
+    let needle = query.needle.unwrap();
+    let mut matcher = nucleo::Matcher::new(nucleo::Config::DEFAULT);
+    assert!(needle.matches("hello world mime:", &mut matcher).is_some());
+
+    // the spacing of the needle is kept
+    let query = SearchQuery::parse("a  b\tc fav:no", SearchMode::Substring, now);
+    let needle = query.needle.unwrap();
+    assert!(needle.matches("a  b\tc", &mut matcher).is_some());
+    assert!(needle.matches("a b c", &mut matcher).is_none());
+
+    let query = SearchQuery::parse("/a  b type:text", SearchMode::Substring, now);
+    let needle = query.needle.unwrap();
+    assert!(needle.matches("a  b", &mut matcher).is_some());
+    assert!(needle.matches("a b", &mut matcher).is_none());
+
+    let query = SearchQuery::parse("after:thursday", SearchMode::Substring, now);
+    assert_eq!(query.filters, vec![Filter::After(day(2026, 10, 15))]);
+    assert!(query.needle.is_none());
+
+    let query = SearchQuery::parse("size:<=18446744073709551615", SearchMode::Substring, now);
+    assert_eq!(query.filters, vec![Filter::SizeLess(usize::MAX)]);
+}
+
+#[tokio::test]
+#[serial]
+async fn external_change() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    db.insert_with_time(build_content(&[("text/plain", "hello")]), 1)
+        .await?;
+    db.insert_with_time(build_content(&[("text/plain", "hello world")]), 2)
+        .await?;
+    assert_eq!(search_ids(&mut db, "hello").await, vec![2, 1]);
+
+    // our own changes
+    assert!(!db.handle_message(DbMessage::CheckUpdate).await?);
+
+    // by another instance
+    let mut conn = connect(&db_dir).await?;
+    sqlx::query("DELETE FROM ClipboardEntries WHERE id = 2")
+        .execute(&mut conn)
+        .await?;
+
+    assert!(db.handle_message(DbMessage::CheckUpdate).await?);
+    assert_eq!(db.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn atomic_insert() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    db.insert_with_time(build_content(&[("text/plain", "hello")]), 1)
+        .await?;
+
+    // fail after the entry and its first content were written
+    let mut conn = connect(&db_dir).await?;
+    sqlx::query(
+        r#"
+        CREATE TRIGGER fail BEFORE INSERT ON ClipboardContents
+        WHEN NEW.mime = 'text/z'
+        BEGIN
+            SELECT RAISE(ABORT, 'fail');
+        END;
+        "#,
+    )
+    .execute(&mut conn)
+    .await?;
+
+    let data = build_content(&[("text/plain", "world"), ("text/z", "world")]);
+    assert!(db.insert_with_time(data, 2).await.is_err());
+    assert_eq!(db.len(), 1);
+
+    let (entries,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM ClipboardEntries")
+        .fetch_one(&mut conn)
+        .await?;
+    let (contents,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM ClipboardContents")
+        .fetch_one(&mut conn)
+        .await?;
+    assert_eq!((entries, contents), (1, 1));
+
+    Ok(())
+}
+
 fn remove_dir_contents(dir: &Path) {
     pub fn inner(dir: &Path) -> Result<(), std::io::Error> {
         for entry in fs::read_dir(dir)?.flatten() {
@@ -265,7 +525,1026 @@ async fn bench_search_from_system_path() {
 
     println!("{}", db.len());
 
-    db.set_query_and_search("a".into());
+    db.set_query_and_search("a".into()).await;
 
     println!("Elapsed: {:?}", now.elapsed());
 }
+
+#[tokio::test]
+#[serial]
+async fn search_fts_prefilter() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    let contents = [
+        "hello world",
+        "Hello World",
+        "un été chaud",
+        "<img alt=\"a cat\">",
+        "he said \"hi there\"",
+    ];
+
+    for (i, content) in contents.iter().enumerate() {
+        let mime = if content.starts_with('<') {
+            "text/html"
+        } else {
+            "text/plain"
+        };
+        db.insert_with_time(build_content(&[(mime, content)]), (i as i64 + 1) * 1000)
+            .await?;
+    }
+
+    let queries = [
+        "world", "World", "ete", "été", "cat", "\"hi the", "^hello", "wor", "wo", "~wrld",
+        "/w.rld", "nothing",
+    ];
+
+    for query in queries {
+        db.fts_prefilter = Some(true);
+        db.set_query_and_search(query.into()).await;
+        let with_fts = db.search_iter().map(|e| e.id).collect::<Vec<_>>();
+
+        db.fts_prefilter = Some(false);
+        db.search().await;
+        let without_fts = db.search_iter().map(|e| e.id).collect::<Vec<_>>();
+
+        assert_eq!(with_fts, without_fts, "query: {query}");
+    }
+
+    // the index follow deletions
+    db.fts_prefilter = Some(true);
+    db.set_query_and_search("world".into()).await;
+    let id = db.search_iter().next().unwrap().id;
+    db.delete(id).await?;
+    assert_eq!(db.search_iter().count(), 1);
+
+    db.clear(true).await?;
+    db.set_query_and_search("world".into()).await;
+    assert_eq!(db.search_iter().count(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn background_search() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    for i in 0..600 {
+        let content = if i % 2 == 0 { "hello" } else { "world" };
+        db.insert_with_time(
+            build_content(&[("text/plain", &format!("{content} {i}"))]),
+            i + 1,
+        )
+        .await?;
+    }
+
+    // match every entry in the background, to get several chunks
+    db.fts_prefilter = Some(false);
+
+    db.set_query("hello".into());
+    let cancelled = db.start_search().unwrap();
+
+    db.set_query("world".into());
+    let current = db.start_search().unwrap();
+
+    // results of the cancelled search are ignored
+    for results in cancelled.collect::<Vec<_>>().await {
+        db.apply_search_results(results);
+    }
+    assert_eq!(db.len(), 0);
+
+    let results = current.collect::<Vec<_>>().await;
+    assert!(results.len() > 1);
+    assert!(results[0].first && !results[1].first);
+
+    for results in results {
+        db.apply_search_results(results);
+    }
+    assert_eq!(db.len(), 300);
+    assert_eq!(db.search_iter().next().unwrap().id, 600);
+
+    // a deleted entry is removed from the results
+    db.delete(600).await?;
+    assert_eq!(db.len(), 299);
+
+    // an empty query clears the results
+    db.set_query(String::new());
+    assert!(db.start_search().is_none());
+    assert_eq!(db.len(), 599);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn ranked_search_chunks() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    // 3 scores, spread over several chunks
+    let kinds = ["abc", "ab_c", "a__b__c"];
+
+    for i in 0..1200 {
+        let content = format!("{} {i}", kinds[i % 3]);
+        db.insert_with_time(build_content(&[("text/plain", &content)]), i as i64 + 1)
+            .await?;
+    }
+
+    db.fts_prefilter = Some(false);
+
+    // by score, then in the order of the history
+    let expected = (0..3)
+        .flat_map(|kind| (0..1200).rev().filter(move |i| i % 3 == kind))
+        .map(|i| i as EntryId + 1)
+        .collect::<Vec<_>>();
+
+    assert_eq!(search_ids(&mut db, "~abc").await, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn recognized_text() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    let image = [("image/png".to_string(), vec![1, 2, 3])].into();
+    let id = db.insert_with_time(image, 1000).await?.unwrap();
+    db.insert_with_time(build_content(&[("text/plain", "hello world")]), 2000)
+        .await?;
+
+    assert_eq!(search_ids(&mut db, "invoice").await, Vec::<EntryId>::new());
+
+    db.set_recognized_text(id, "Invoice 42".into()).await?;
+
+    assert_eq!(search_ids(&mut db, "invoice").await, vec![id]);
+    assert_eq!(
+        db.get_search_match(id).unwrap().mime,
+        super::RECOGNIZED_TEXT_MIME
+    );
+    assert_eq!(search_ids(&mut db, "type:image 42").await, vec![id]);
+
+    // persisted
+    db.reload().await?;
+    assert_eq!(
+        db.get_from_id(id).unwrap().recognized_text.as_deref(),
+        Some("Invoice 42")
+    );
+
+    // indexed for the prefilter, and replaced
+    db.fts_prefilter = Some(true);
+    assert_eq!(search_ids(&mut db, "invoice").await, vec![id]);
+
+    db.set_recognized_text(id, "Receipt 7".into()).await?;
+    assert_eq!(search_ids(&mut db, "invoice").await, Vec::<EntryId>::new());
+    assert_eq!(search_ids(&mut db, "receipt").await, vec![id]);
+
+    Ok(())
+}
+
+/// Handles the clipboard messages like the applet.
+struct Capture {
+    messages: Pin<Box<dyn Stream<Item = ClipboardMessage> + Send>>,
+    handler: ClipboardHandler,
+    /// Entries are ordered by their creation time, in ms
+    time: i64,
+}
+
+impl Capture {
+    fn new(clipboard: &MockBackend) -> Self {
+        let connect = {
+            let clipboard = clipboard.clone();
+            move || Ok(clipboard.clone())
+        };
+        let backoff = clipboard::Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        };
+
+        Capture {
+            messages: Box::pin(clipboard::supervise(connect, backoff)),
+            handler: ClipboardHandler::default(),
+            time: utils::now_millis(),
+        }
+    }
+
+    /// Handle the messages until `count` offers were captured or restored.
+    async fn run(&mut self, db: &mut DbSqlite, count: usize) -> Result<()> {
+        let mut handled = 0;
+
+        while handled < count {
+            let message = self.messages.next().await.unwrap();
+            self.time += 1;
+
+            match self.handler.handle(db, message, self.time, true).await? {
+                Handled::Captured(_) => handled += 1,
+                Handled::Restore(data) => {
+                    self.handler
+                        .selection
+                        .as_ref()
+                        .unwrap()
+                        .set_selection(data, false)?;
+                    handled += 1;
+                }
+                Handled::Failed(e) => panic!("{e}"),
+                Handled::Nothing | Handled::Filtered => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn contents(db: &DbSqlite) -> Vec<MimeDataMap> {
+    db.iter().map(|entry| entry.raw_content().clone()).collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn clipboard_reports() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+    let mut handler = ClipboardHandler::default();
+
+    let mut handle =
+        async |message, time| match handler.handle(&mut db, message, time, false).await.unwrap() {
+            Handled::Nothing => "nothing",
+            Handled::Captured(_) => "captured",
+            Handled::Restore(_) => "restore",
+            Handled::Failed(_) => "failed",
+            Handled::Filtered => "filtered",
+        };
+
+    // a flapping watcher fails once, until an offer is captured
+    let mut reports = Vec::new();
+    for _ in 0..3 {
+        reports.push(handle(ClipboardMessage::Connected(None), 0).await);
+        reports.push(handle(ClipboardMessage::Error(ClipboardError::Stopped), 0).await);
+    }
+    reports.push(handle(ClipboardMessage::Connected(None), 0).await);
+    reports.push(
+        handle(
+            ClipboardMessage::Data(build_content(&[("text/plain", "a")])),
+            1,
+        )
+        .await,
+    );
+    reports.push(handle(ClipboardMessage::Error(ClipboardError::Stopped), 2).await);
+    assert_eq!(
+        reports,
+        vec![
+            "nothing", "failed", "nothing", "nothing", "nothing", "nothing", "nothing", "captured",
+            "failed"
+        ]
+    );
+
+    // the filtered offers are reported at most once per interval
+    let mut reports = Vec::new();
+    for time in [
+        10,
+        20,
+        10 + FILTERED_REPORT_INTERVAL - 1,
+        10 + FILTERED_REPORT_INTERVAL,
+        20 + FILTERED_REPORT_INTERVAL,
+    ] {
+        reports.push(handle(ClipboardMessage::Filtered, time).await);
+    }
+    assert_eq!(
+        reports,
+        vec!["filtered", "nothing", "nothing", "filtered", "nothing"]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn clipboard_capture() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    let a = build_content(&[("text/plain", "a"), ("text/html", "<p>a</p>")]);
+    let b = build_content(&[("text/plain", "b")]);
+
+    let clipboard = MockBackend::default();
+    clipboard.copy(a.clone());
+    clipboard.copy(b.clone());
+    clipboard.copy(a.clone());
+    clipboard.clear();
+
+    let mut capture = Capture::new(&clipboard);
+
+    // 3 copies, then the source exits
+    capture.run(&mut db, 4).await?;
+
+    clipboard.disconnect();
+
+    assert_eq!(contents(&db), vec![a.clone(), b]);
+    assert_eq!(clipboard.selection(), Some(own_offer(a)));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn keep_clipboard_alive() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    let a = build_content(&[("text/plain", "a")]);
+    let b = build_content(&[("text/plain", "b"), ("text/html", "<p>b</p>")]);
+    let c = build_content(&[("image/png", "c"), ("text/uri-list", "file:///c.png")]);
+
+    let clipboard = MockBackend::default();
+    let mut capture = Capture::new(&clipboard);
+
+    clipboard.copy(a.clone());
+    capture.run(&mut db, 1).await?;
+    let a_id = db.last().unwrap().id;
+    db.add_favorite(a_id, None).await?;
+
+    // the favorite comes first, but b was copied last
+    clipboard.copy(b.clone());
+    clipboard.clear();
+    capture.run(&mut db, 2).await?;
+    assert_eq!(db.get(0).unwrap().id, a_id);
+    assert_eq!(clipboard.selection(), Some(own_offer(b.clone())));
+
+    // with all its mime types
+    clipboard.copy(c.clone());
+    clipboard.clear();
+    capture.run(&mut db, 2).await?;
+    assert_eq!(clipboard.selection(), Some(own_offer(c.clone())));
+
+    // the restored offer wasn't captured again
+    assert_eq!(contents(&db), vec![a, c.clone(), b.clone()]);
+
+    // deleted, fallback to the most recent entry
+    let c_id = db.last().unwrap().id;
+    db.delete(c_id).await?;
+    clipboard.clear();
+    capture.run(&mut db, 1).await?;
+    assert_eq!(clipboard.selection(), Some(own_offer(b.clone())));
+
+    // nothing captured yet, after a restart
+    assert_eq!(KeepAlive::default().content(&db), Some(b));
+
+    clipboard.disconnect();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+#[ignore = "bench"]
+async fn bench_search_fts_10k() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    for i in 0..10_000 {
+        let content = format!(
+            "entry {i}: lorem ipsum dolor sit amet, consectetur adipiscing elit {}",
+            "sed do eiusmod tempor ".repeat(i % 50)
+        );
+        db.insert_with_time(build_content(&[("text/plain", &content)]), i as i64 + 1)
+            .await?;
+    }
+
+    for query in ["9999", "entry 42", "tempor sed", "nothing here"] {
+        let mut results = Vec::new();
+
+        // the scan, then the prefilter when it is selective enough
+        for fts_prefilter in [Some(false), None] {
+            db.fts_prefilter = fts_prefilter;
+
+            let now = Instant::now();
+            db.set_query_and_search(query.into()).await;
+
+            println!(
+                "query: {query:?}, fts: {fts_prefilter:?}, results: {}, elapsed: {:?}",
+                db.search_iter().count(),
+                now.elapsed()
+            );
+
+            results.push(db.search_iter().map(|e| e.id).collect::<Vec<_>>());
+        }
+
+        // the prefilter doesn't change the results
+        assert_eq!(results[0], results[1]);
+    }
+
+    Ok(())
+}
+
+async fn connect(db_dir: &Path) -> Result<SqliteConnection> {
+    let db_path = db_dir.join(DB_FILENAME);
+    let url = format!("sqlite://{}?mode=rwc", db_path.display());
+
+    Ok(SqliteConnection::connect(&url).await?)
+}
+
+/// Number of blobs, and their total size.
+async fn blobs_size(conn: &mut SqliteConnection) -> Result<(i64, i64)> {
+    let row = sqlx::query("SELECT COUNT(*), COALESCE(SUM(LENGTH(content)), 0) FROM ClipboardBlobs")
+        .fetch_one(conn)
+        .await?;
+
+    Ok((row.get(0), row.get(1)))
+}
+
+#[tokio::test]
+#[serial]
+async fn blobs_dedup() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+    let mut conn = connect(&db_dir).await?;
+
+    let text = "a".repeat(10_000);
+
+    let a = build_content(&[
+        ("text/plain", &text),
+        ("text/plain;charset=utf-8", &text),
+        ("UTF8_STRING", &text),
+        ("STRING", &text),
+        ("TEXT", &text),
+    ]);
+    let b = build_content(&[("text/plain", "short"), ("text/html", &text)]);
+
+    db.insert_with_time(a.clone(), 1).await?;
+    assert_eq!(blobs_size(&mut conn).await?, (1, 10_000));
+
+    // shared with another entry
+    db.insert_with_time(b.clone(), 2).await?;
+    assert_eq!(blobs_size(&mut conn).await?, (2, 10_005));
+
+    db.reload().await?;
+    assert_eq!(contents(&db), vec![b.clone(), a.clone()]);
+
+    // released with the last entry using it, once purged
+    db.trash_grace_period = 0;
+
+    db.delete(1).await?;
+    db.handle_message(DbMessage::CheckUpdate).await?;
+    assert_eq!(blobs_size(&mut conn).await?, (2, 10_005));
+    assert_eq!(search_ids(&mut db, "aaa").await, vec![2]);
+
+    db.delete(2).await?;
+    db.handle_message(DbMessage::CheckUpdate).await?;
+    assert_eq!(blobs_size(&mut conn).await?, (0, 0));
+    assert!(search_ids(&mut db, "aaa").await.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn migrate_blobs() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    // the database before the blobs
+    let old_migrations = db_dir.join("old_migrations");
+    fs::create_dir_all(&old_migrations)?;
+
+    for file in fs::read_dir("migrations")? {
+        let file = file?;
+        if file.file_name().to_str().unwrap() < "20261020120000" {
+            fs::copy(file.path(), old_migrations.join(file.file_name()))?;
+        }
+    }
+
+    let mut conn = connect(&db_dir).await?;
+
+    sqlx::migrate::Migrator::new(old_migrations)
+        .await?
+        .run(&mut conn)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO ClipboardEntries (id, creation)
+        VALUES (1, 1), (2, 2);
+
+        INSERT INTO ClipboardContents (id, mime, content)
+        VALUES
+            (1, 'text/plain', CAST('hello world' AS BLOB)),
+            (1, 'UTF8_STRING', CAST('hello world' AS BLOB)),
+            (2, 'text/plain', CAST('bye' AS BLOB)),
+            (2, 'image/png', CAST('png' AS BLOB));
+        "#,
+    )
+    .execute(&mut conn)
+    .await?;
+
+    // the entries are too old otherwise
+    let config = Config {
+        maximum_entries_lifetime: None,
+        ..Default::default()
+    };
+    let mut db = DbSqlite::with_path(&config, &db_dir).await?;
+
+    assert_eq!(
+        contents(&db),
+        vec![
+            build_content(&[("text/plain", "bye"), ("image/png", "png")]),
+            build_content(&[
+                ("text/plain", "hello world"),
+                ("UTF8_STRING", "hello world")
+            ]),
+        ]
+    );
+    assert_eq!(blobs_size(&mut conn).await?, (3, 17));
+
+    let missing_hashes: i64 = sqlx::query("SELECT COUNT(*) FROM ClipboardBlobs WHERE hash IS NULL")
+        .fetch_one(&mut conn)
+        .await?
+        .get(0);
+    assert_eq!(missing_hashes, 0);
+
+    assert_eq!(search_ids(&mut db, "world").await, vec![1]);
+
+    // deduplicated with the migrated blobs
+    db.insert_with_time(
+        build_content(&[("text/plain", "hello"), ("text/html", "hello world")]),
+        3,
+    )
+    .await?;
+    assert_eq!(blobs_size(&mut conn).await?, (4, 22));
+
+    // more than a batch to hash
+    for i in 0..HASH_BATCH * 2 {
+        db.insert_with_time(build_content(&[("text/plain", &i.to_string())]), 10 + i)
+            .await?;
+    }
+
+    sqlx::query("UPDATE ClipboardBlobs SET hash = NULL")
+        .execute(&mut conn)
+        .await?;
+    drop(db);
+
+    let _db = DbSqlite::with_path(&config, &db_dir).await?;
+
+    let missing_hashes: i64 = sqlx::query("SELECT COUNT(*) FROM ClipboardBlobs WHERE hash IS NULL")
+        .fetch_one(&mut conn)
+        .await?
+        .get(0);
+    assert_eq!(missing_hashes, 0);
+
+    Ok(())
+}
+
+async fn entry_hash(conn: &mut SqliteConnection, id: EntryId) -> Result<Option<Vec<u8>>> {
+    let row = sqlx::query("SELECT hash FROM ClipboardEntries WHERE id = ?")
+        .bind(id)
+        .fetch_one(conn)
+        .await?;
+
+    Ok(row.get("hash"))
+}
+
+#[tokio::test]
+#[serial]
+async fn persisted_hash() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let config = Config {
+        maximum_entries_lifetime: None,
+        ..Default::default()
+    };
+
+    let mut db = DbSqlite::with_path(&config, &db_dir).await?;
+    let mut conn = connect(&db_dir).await?;
+
+    let data = build_content(&[("text/plain", "hello"), ("text/html", "<p>hello</p>")]);
+    db.insert_with_time(data.clone(), 1).await?;
+
+    // each mime type and content, after its length
+    let mut expected = blake3::Hasher::new();
+    for bytes in [&b"text/plain"[..], b"hello", b"text/html", b"<p>hello</p>"] {
+        expected.update(&(bytes.len() as u64).to_le_bytes());
+        expected.update(bytes);
+    }
+    let expected = expected.finalize().as_bytes().to_vec();
+
+    assert_eq!(entry_hash(&mut conn, 1).await?, Some(expected.clone()));
+
+    // more than a batch
+    for i in 0..HASH_BATCH * 2 {
+        db.insert_with_time(build_content(&[("text/plain", &i.to_string())]), 10 + i)
+            .await?;
+    }
+
+    // inserted by a previous version
+    sqlx::query("UPDATE ClipboardEntries SET hash = NULL")
+        .execute(&mut conn)
+        .await?;
+    drop(db);
+
+    let mut db = DbSqlite::with_path(&config, &db_dir).await?;
+    assert_eq!(entry_hash(&mut conn, 1).await?, Some(expected));
+
+    let (missing,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM ClipboardEntries WHERE hash IS NULL")
+            .fetch_one(&mut conn)
+            .await?;
+    assert_eq!(missing, 0);
+
+    // deduplicated after a restart
+    let len = db.len();
+    db.insert_with_time(data.clone(), 1000).await?;
+    assert_eq!(db.len(), len);
+    assert_eq!(db.last().unwrap().creation, 1000);
+
+    // the same text with other plain text mime types
+    let bridged = build_content(&[("UTF8_STRING", "hello"), ("text/html", "<p>hello</p>")]);
+    db.insert_with_time(bridged, 1001).await?;
+    assert_eq!(db.len(), len);
+
+    // the html is not lost
+    db.insert_with_time(build_content(&[("text/plain", "hello")]), 1002)
+        .await?;
+    assert_eq!(db.len(), len + 1);
+    assert!(db.iter().any(|entry| *entry.raw_content == data));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn near_duplicates() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let config = Config {
+        duplicates_ignore_whitespace: true,
+        ..Default::default()
+    };
+
+    let mut db = DbSqlite::with_path(&config, &db_dir).await?;
+
+    let text = |text| build_content(&[("text/plain", text)]);
+
+    // replaced by the most recent copy
+    db.insert_with_time(text("hello world"), 1).await?;
+    db.insert_with_time(text("  hello   world\n"), 2).await?;
+    assert_eq!(contents(&db), vec![text("  hello   world\n")]);
+
+    db.insert_with_time(text("Hello World"), 3).await?;
+    assert_eq!(db.len(), 2);
+
+    db.set_normalization(Normalization {
+        whitespace: true,
+        ignore_case: true,
+    });
+
+    db.insert_with_time(text("HELLO world "), 4).await?;
+    assert_eq!(
+        contents(&db),
+        vec![text("HELLO world "), text("  hello   world\n")]
+    );
+
+    // the most recent is kept
+    assert_eq!(db.merge_duplicates().await?, 1);
+    assert_eq!(contents(&db), vec![text("HELLO world ")]);
+
+    // a favorite keeps its content
+    db.add_favorite(4, None).await?;
+    db.insert_with_time(text("hello world"), 5).await?;
+    assert_eq!(contents(&db), vec![text("HELLO world ")]);
+    assert_eq!(db.last().unwrap().creation, 5);
+
+    // the images are compared exactly
+    db.insert_with_time(build_content(&[("image/png", "a")]), 6)
+        .await?;
+    db.insert_with_time(build_content(&[("image/png", "A")]), 7)
+        .await?;
+    assert_eq!(db.len(), 3);
+
+    // the most recent is kept, even when the other one was used more
+    db.set_normalization(Normalization::default());
+    db.insert_with_time(text("abc"), 8).await?;
+    db.insert_with_time(text("ABC"), 9).await?;
+    db.mark_used(8, CopyPolicy::KeepPosition).await?;
+    db.set_sort_order(SortOrder::MostUsed);
+
+    db.set_normalization(Normalization {
+        whitespace: true,
+        ignore_case: true,
+    });
+    assert_eq!(db.merge_duplicates().await?, 1);
+    assert!(db.get_from_id(9).is_some());
+    assert!(db.get_from_id(8).is_none());
+
+    // the merge can be undone
+    db.set_normalization(Normalization::default());
+    assert_eq!(db.undo_delete().await?, 1);
+    assert!(db.get_from_id(8).is_some());
+
+    Ok(())
+}
+
+fn ids(db: &DbSqlite) -> Vec<EntryId> {
+    db.iter().map(|entry| entry.id).collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn usage() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    for (i, text) in ["alpha one", "beta one", "gamma one"].iter().enumerate() {
+        db.insert_with_time(build_content(&[("text/plain", text)]), i as i64 + 1)
+            .await?;
+    }
+
+    db.mark_used(1, CopyPolicy::KeepPosition).await?;
+    db.mark_used(1, CopyPolicy::KeepPosition).await?;
+    db.mark_used(2, CopyPolicy::KeepPosition).await?;
+
+    assert_eq!(ids(&db), vec![3, 2, 1]);
+    assert_eq!(search_ids(&mut db, "one").await, vec![3, 2, 1]);
+
+    db.set_sort_order(SortOrder::MostUsed);
+    assert_eq!(ids(&db), vec![1, 2, 3]);
+
+    // copied recently, and more often
+    assert_eq!(search_ids(&mut db, "one").await, vec![1, 2, 3]);
+
+    // persisted
+    db.reload().await?;
+    let entry = db.get_from_id(1).unwrap();
+    assert_eq!(entry.copy_count, 2);
+    assert!(entry.last_used.is_some());
+    assert_eq!(ids(&db), vec![1, 2, 3]);
+
+    // a capture bumps the recency, not the usage
+    db.insert_with_time(build_content(&[("text/plain", "beta one")]), 4)
+        .await?;
+    assert_eq!(ids(&db), vec![1, 2, 3]);
+    db.set_sort_order(SortOrder::Recent);
+    assert_eq!(ids(&db), vec![2, 3, 1]);
+
+    // the favorites stay first
+    db.set_sort_order(SortOrder::MostUsed);
+    db.add_favorite(3, None).await?;
+    assert_eq!(ids(&db), vec![3, 1, 2]);
+
+    // the filters keep the order of the history
+    assert_eq!(search_ids(&mut db, "size:<1KB").await, vec![3, 1, 2]);
+
+    // the ranked results, by score and frecency, whatever the sort order
+    db.remove_favorite(3).await?;
+    db.set_sort_order(SortOrder::Recent);
+    assert_eq!(search_ids(&mut db, "~one").await, vec![1, 2, 3]);
+    assert_eq!(search_ids(&mut db, "~gamma").await, vec![3]);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn copy_policy() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    for (i, text) in ["a", "b", "c"].iter().enumerate() {
+        db.insert_with_time(build_content(&[("text/plain", text)]), i as i64 + 1)
+            .await?;
+    }
+
+    db.mark_used(1, CopyPolicy::KeepPosition).await?;
+    assert_eq!(ids(&db), vec![3, 2, 1]);
+
+    db.mark_used(1, CopyPolicy::BumpToTop).await?;
+    assert_eq!(ids(&db), vec![1, 3, 2]);
+    assert_eq!(db.last().unwrap().id, 1);
+    assert_eq!(db.get_from_id(1).unwrap().copy_count, 2);
+
+    // persisted
+    db.reload().await?;
+    assert_eq!(ids(&db), vec![1, 3, 2]);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn trash() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+    let mut conn = connect(&db_dir).await?;
+
+    for (i, text) in ["a", "b", "c"].iter().enumerate() {
+        db.insert_with_time(build_content(&[("text/plain", text)]), i as i64 + 1)
+            .await?;
+    }
+
+    db.delete(3).await?;
+    assert_eq!(ids(&db), vec![2, 1]);
+    assert_eq!(db.undo_delete().await?, 1);
+    assert_eq!(ids(&db), vec![3, 2, 1]);
+
+    // the favorites are kept
+    db.add_favorite(1, None).await?;
+    db.clear(true).await?;
+    assert_eq!(ids(&db), vec![1]);
+
+    assert_eq!(db.undo_delete().await?, 2);
+    assert_eq!(ids(&db), vec![1, 3, 2]);
+
+    // the deleted favorites are restored at their position, one deletion at a time,
+    // even in the same millisecond
+    db.add_favorite(3, Some(0)).await?;
+    db.delete(1).await?;
+    db.delete(3).await?;
+    assert_eq!(ids(&db), vec![2]);
+    assert_eq!(db.undo_delete().await?, 1);
+    assert_eq!(ids(&db), vec![3, 2]);
+    assert_eq!(db.undo_delete().await?, 1);
+    assert_eq!(ids(&db), vec![3, 1, 2]);
+    assert!(db.get_from_id(1).unwrap().is_favorite());
+    db.remove_favorite(3).await?;
+
+    // copied again meanwhile
+    db.delete(2).await?;
+    db.insert_with_time(build_content(&[("text/plain", "b")]), 4)
+        .await?;
+    assert_eq!(db.undo_delete().await?, 0);
+    assert_eq!(ids(&db), vec![1, 4, 3]);
+
+    // hidden after a restart
+    db.reload().await?;
+    assert_eq!(ids(&db), vec![1, 4, 3]);
+
+    // purged after the grace period, with the favorites
+    db.add_favorite(4, None).await?;
+    db.trash_grace_period = 0;
+    db.delete_many(&[1, 3]).await?;
+    db.handle_message(DbMessage::CheckUpdate).await?;
+    assert_eq!(db.undo_delete().await?, 0);
+
+    let count: i64 = sqlx::query("SELECT COUNT(*) FROM ClipboardEntries")
+        .fetch_one(&mut conn)
+        .await?
+        .get(0);
+    assert_eq!(count, 1);
+
+    let positions: Vec<i64> = sqlx::query_scalar("SELECT position FROM FavoriteClipboardEntries")
+        .fetch_all(&mut conn)
+        .await?;
+    assert_eq!(positions, vec![0]);
+
+    db.reload().await?;
+    assert_eq!(ids(&db), vec![4]);
+
+    // a permanent clear empties the trash too
+    db.trash_grace_period = 60 * 1000;
+    db.insert_with_time(build_content(&[("text/plain", "e")]), 5)
+        .await?;
+    db.insert_with_time(build_content(&[("text/plain", "f")]), 6)
+        .await?;
+    db.delete(5).await?;
+    db.clear(false).await?;
+    assert_eq!(ids(&db), vec![4]);
+    assert_eq!(db.undo_delete().await?, 0);
+
+    let count: i64 = sqlx::query("SELECT COUNT(*) FROM ClipboardEntries")
+        .fetch_one(&mut conn)
+        .await?
+        .get(0);
+    assert_eq!(count, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn bulk_operations() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    db.insert_with_time(build_content(&[("text/plain", "a")]), 1)
+        .await?;
+    db.insert_with_time(build_content(&[("image/png", "b")]), 2)
+        .await?;
+    db.insert_with_time(
+        build_content(&[("text/html", "<p>c</p>"), ("UTF8_STRING", "c")]),
+        3,
+    )
+    .await?;
+
+    let entries = [1, 2, 3].map(|id| db.get_from_id(id).unwrap());
+    let data = concatenate(entries, ", ").unwrap();
+    assert_eq!(data["text/plain"], b"a, c");
+    assert_eq!(data["text/plain;charset=utf-8"], b"a, c");
+
+    assert!(concatenate([db.get_from_id(2).unwrap()], ", ").is_none());
+
+    // restored together
+    db.delete_many(&[1, 3]).await?;
+    assert_eq!(ids(&db), vec![2]);
+    assert_eq!(db.undo_delete().await?, 2);
+    assert_eq!(ids(&db), vec![3, 2, 1]);
+
+    // one file per entry, in the given order, with its preferred content
+    db.insert_with_time(build_content(&[("application/x-unknown", "d")]), 4)
+        .await?;
+    let dir = export::export(
+        [1, 4, 2, 3].map(|id| db.get_from_id(id).unwrap()),
+        &[Regex::new("text/html")?],
+        &db_dir,
+    )?;
+
+    let mut files = std::fs::read_dir(&dir)?
+        .map(|file| {
+            let path = file?.path();
+            let content = std::fs::read(&path)?;
+            Ok((
+                path.file_name().unwrap().to_string_lossy().into_owned(),
+                content,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            ("1.txt".into(), b"a".to_vec()),
+            ("3.png".into(), b"b".to_vec()),
+            ("4.html".into(), b"<p>c</p>".to_vec()),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn delete_range() -> Result<()> {
+    let db_dir = prepare_db_dir();
+
+    let mut db = DbSqlite::with_path(&Config::default(), &db_dir).await?;
+
+    for i in 1..=5 {
+        db.insert_with_time(build_content(&[("text/plain", &i.to_string())]), i * 10)
+            .await?;
+    }
+    db.add_favorite(40, None).await?;
+
+    assert_eq!(db.delete_range(30, i64::MAX, true).await?, 2);
+    assert_eq!(ids(&db), vec![40, 20, 10]);
+
+    assert_eq!(db.delete_range(i64::MIN, 20, true).await?, 1);
+    assert_eq!(ids(&db), vec![40, 20]);
+
+    assert_eq!(db.delete_range(30, 10, true).await?, 0);
+
+    // copied again, after the clear
+    db.insert_with_time(build_content(&[("text/plain", "5")]), 60)
+        .await?;
+    assert_eq!(ids(&db), vec![40, 60, 20]);
+
+    // without the trash, like from the command line
+    let mut conn = connect(&db_dir).await?;
+    let count = async |conn: &mut SqliteConnection| -> Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COUNT(*) FROM ClipboardEntries")
+            .fetch_one(conn)
+            .await?)
+    };
+    assert_eq!(count(&mut conn).await?, 6);
+
+    assert_eq!(db.delete_range(i64::MIN, 30, false).await?, 1);
+    assert_eq!(ids(&db), vec![40, 60]);
+    assert_eq!(count(&mut conn).await?, 5);
+
+    // only the previous deletion can be undone
+    assert_eq!(db.undo_delete().await?, 1);
+    assert_eq!(ids(&db), vec![40, 60, 10]);
+
+    let minute = Duration::from_secs(60);
+    assert_eq!(
+        ClearRange::Last(15 * minute).bounds(3_600_000),
+        (2_700_000, i64::MAX)
+    );
+    assert_eq!(
+        ClearRange::OlderThan(15 * minute).bounds(3_600_000),
+        (i64::MIN, 2_700_000)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Export of entries to files, one per entry.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::{db::EntryTrait, utils};
+
+/// Extension of the files with this mime type.
+fn extension(mime: &str) -> &str {
+    match mime {
+        "text/html" => "html",
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/svg+xml" => "svg",
+        m if m.starts_with("image/") => &m["image/".len()..],
+        // the X11 text targets
+        m if m.starts_with("text/") || !m.contains('/') => "txt",
+        _ => "bin",
+    }
+}
+
+/// Write the preferred content of each entry in a new directory of `parent`,
+/// and return it. The entries without content are skipped.
+pub fn export<'a, E: EntryTrait + 'a>(
+    entries: impl IntoIterator<Item = &'a E>,
+    preferred_mime_types: &[Regex],
+    parent: &Path,
+) -> Result<PathBuf> {
+    let dir = parent.join(format!("clipboard-{}", utils::now_millis()));
+
+    fs::create_dir_all(&dir).with_context(|| format!("can't create {}", dir.display()))?;
+
+    for (i, entry) in entries.into_iter().enumerate() {
+        let Some(((mime, content), _)) = entry.preferred_content(preferred_mime_types) else {
+            continue;
+        };
+
+        let path = dir.join(format!("{}.{}", i + 1, extension(mime)));
+
+        fs::write(&path, content).with_context(|| format!("can't write {}", path.display()))?;
+    }
+
+    Ok(dir)
+}
@@ -6,6 +6,7 @@ use app::{AppState, Flags};
 use config::{CONFIG_VERSION, Config};
 use cosmic::cosmic_config;
 use cosmic::cosmic_config::CosmicConfigEntry;
+use db::{ClearRange, DbTrait, EntryTrait};
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 mod app;
@@ -13,13 +14,18 @@ mod clipboard;
 mod clipboard_watcher;
 mod config;
 mod db;
+mod export;
 mod icon;
 mod localize;
 mod message;
 mod my_widget;
 mod navigation;
+mod notification;
+mod ocr;
+mod selection;
 mod utils;
 mod view;
+mod x11_watcher;
 
 #[allow(unused_imports)]
 #[macro_use]
@@ -46,17 +52,72 @@ fn setup_logs() {
     }
 }
 
+const USAGE: &str = "\
+Usage: cosmic-ext-applet-clipboard-manager [OPTIONS]
+
+Options:
+  --clear-last <DURATION>        Clear the entries copied during the last duration
+  --clear-older-than <DURATION>  Clear the entries copied before the last duration
+  --copy <POSITION>              Copy the entry at this position in the history, from 1
+  --primary                      With --copy, set the primary selection instead
+  -V, --version                  Print the version
+
+The durations are like 30s, 15m, 1h, 2d or 1w. The favorites are kept.
+--copy keeps running until another copy replaces the entry, on Wayland only.
+--copy and the --clear options also work while the applet is running.";
+
+/// Print the usage, and exit with the status of invalid arguments.
+fn usage_error() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
+
 fn main() {
-    for arg in std::env::args().skip(1) {
-        if arg == "-V" || arg == "--version" {
-            let version = env!("CARGO_PKG_VERSION");
-            let commit = option_env!("CLIPBOARD_MANAGER_COMMIT").unwrap_or("unknown");
+    let mut clear_range = None;
+    let mut copy = None;
+    let mut primary = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-V" | "--version" => {
+                let version = env!("CARGO_PKG_VERSION");
+                let commit = option_env!("CLIPBOARD_MANAGER_COMMIT").unwrap_or("unknown");
 
-            println!("clipboard-manager {version} (commit {commit})");
-            return;
+                println!("clipboard-manager {version} (commit {commit})");
+                return;
+            }
+            "--copy" => {
+                let Some(position) = args
+                    .next()
+                    .and_then(|position| position.parse::<usize>().ok())
+                    .filter(|position| *position > 0)
+                else {
+                    usage_error();
+                };
+
+                copy = Some(position);
+            }
+            "--primary" => primary = true,
+            "--clear-last" | "--clear-older-than" => {
+                let Some(duration) = args.next().as_deref().and_then(utils::parse_duration) else {
+                    usage_error();
+                };
+
+                clear_range = Some(if arg == "--clear-last" {
+                    ClearRange::Last(duration)
+                } else {
+                    ClearRange::OlderThan(duration)
+                });
+            }
+            _ => usage_error(),
         }
     }
 
+    if primary && copy.is_none() {
+        usage_error();
+    }
+
     localize::localize();
 
     setup_logs();
@@ -78,6 +139,46 @@ fn main() {
         }
     };
 
+    if let Some(range) = clear_range {
+        let (from, to) = range.bounds(utils::now_millis());
+
+        let res = futures::executor::block_on(async {
+            let mut db = db::DbSqlite::new(&config).await?;
+            // no undo from the command line
+            db.delete_range(from, to, false).await
+        });
+
+        match res {
+            Ok(count) => println!("cleared {count} entries"),
+            Err(e) => {
+                eprintln!("can't clear the entries: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(position) = copy {
+        let res = futures::executor::block_on(async {
+            let mut db = db::DbSqlite::new(&config).await?;
+            let Some(entry) = db.get(position - 1) else {
+                anyhow::bail!("there are only {} entries", db.len());
+            };
+
+            let (id, data) = (entry.id(), entry.raw_content().clone());
+            db.mark_used(id, config.copy_policy()).await?;
+            Ok(data)
+        });
+
+        let res = res.and_then(|data| Ok(clipboard::serve_selection(data, primary)?));
+
+        if let Err(e) = res {
+            eprintln!("can't copy the entry: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let flags = Flags {
         config_handler,
         config,
@@ -1,7 +1,9 @@
+use std::time::Duration;
+
 use crate::{
     clipboard::ClipboardMessage,
     config::Config,
-    db::{DbMessage, EntryId, MimeDataMap},
+    db::{ClearRange, DbMessage, EntryId, MimeDataMap, SearchResults},
     navigation::EventMsg,
 };
 
@@ -12,13 +14,27 @@ pub enum AppMsg {
     ToggleQuickSettings,
     ClosePopup,
     Search(String),
+    SearchResults(SearchResults),
+    TextRecognized(EntryId, String),
+    /// Then recognize the next queued entry
+    QueuedTextRecognized(EntryId, Option<String>),
     ClipboardEvent(ClipboardMessage),
-    #[expect(dead_code)]
     RetryConnectingClipboard,
+    /// Pause the recording for this duration, or until logout
+    Pause(Option<Duration>),
+    Resume,
+    PauseTick,
     Copy(EntryId),
+    /// Copy, or select with the ctrl and shift modifiers
+    EntryPressed(EntryId),
+    Selection(SelectionMsg),
     #[expect(dead_code)]
     CopySpecial(MimeDataMap),
     Clear,
+    ClearRange(ClearRange),
+    UndoDelete(ToastId),
+    CloseToast(ToastId),
+    MergeDuplicates,
     Navigation(EventMsg),
     Db(DbMessage),
     ReturnToClipboard,
@@ -35,10 +51,27 @@ pub enum ContextMenuMsg {
     RemoveFavorite(EntryId),
     AddFavorite(EntryId),
     ShowQrCode(EntryId),
+    CopyRecognizedText(EntryId),
+    CopyToPrimary(EntryId),
     Delete(EntryId),
 }
 
-use cosmic::widget::{markdown, menu::action::MenuAction};
+/// Operations on the selected entries.
+#[derive(Clone, Debug, PartialEq, Eq, Copy)]
+pub enum SelectionMsg {
+    Toggle(EntryId),
+    /// Select the entries from the last toggled one
+    ExtendTo(EntryId),
+    Clear,
+    Delete,
+    AddFavorite,
+    RemoveFavorite,
+    Export,
+    /// Copy the texts as a new entry, with the configured separator
+    CopyConcatenated,
+}
+
+use cosmic::widget::{markdown, menu::action::MenuAction, toaster::ToastId};
 
 impl MenuAction for ContextMenuMsg {
     type Message = AppMsg;
@@ -54,4 +87,16 @@ pub enum ConfigMsg {
     #[expect(dead_code)]
     Horizontal(bool),
     UniqueSession(bool),
+    FuzzySearch(bool),
+    TextRecognition(bool),
+    KeepClipboardAlive(bool),
+    DuplicatesIgnoreWhitespace(bool),
+    DuplicatesIgnoreCase(bool),
+    SortByUsage(bool),
+    KeepCopiedPosition(bool),
+    ConcatenationSeparator(String),
+    NewEntriesBadge(bool),
+    NotifyErrors(bool),
+    NotifyFiltered(bool),
+    NotifyCopied(bool),
 }
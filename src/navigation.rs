@@ -1,6 +1,7 @@
 use cosmic::iced::{
     Subscription,
     event::{self},
+    keyboard::Modifiers,
 };
 
 #[derive(Debug, Clone)]
@@ -10,6 +11,7 @@ pub enum EventMsg {
     Enter,
     Quit,
     Event(cosmic::iced::keyboard::key::Named),
+    Modifiers(Modifiers),
     None,
 }
 
@@ -38,7 +40,9 @@ pub fn sub() -> Subscription<EventMsg> {
                                     | cosmic::iced::keyboard::key::Named::ArrowDown
                                     | cosmic::iced::keyboard::key::Named::ArrowUp
                                     | cosmic::iced::keyboard::key::Named::ArrowLeft
-                                    | cosmic::iced::keyboard::key::Named::ArrowRight => {
+                                    | cosmic::iced::keyboard::key::Named::ArrowRight
+                                    | cosmic::iced::keyboard::key::Named::Space
+                                    | cosmic::iced::keyboard::key::Named::Delete => {
                                         Some(EventMsg::Event(named))
                                     }
 
@@ -49,8 +53,6 @@ pub fn sub() -> Subscription<EventMsg> {
 
                                     cosmic::iced::keyboard::key::Named::Clear => todo!(),
 
-                                    cosmic::iced::keyboard::key::Named::Delete => todo!(),
-
                                     cosmic::iced::keyboard::key::Named::Cancel => todo!(),
 
 
@@ -94,6 +96,9 @@ pub fn sub() -> Subscription<EventMsg> {
                                 _ => None,
                             }
                         }
+                        cosmic::iced::keyboard::Event::ModifiersChanged(modifiers) => {
+                            Some(EventMsg::Modifiers(modifiers))
+                        }
                         _ => None,
                     },
                     _ => None,
@@ -0,0 +1,133 @@
+//! Desktop notifications, with the `org.freedesktop.Notifications` D-Bus interface.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use tokio::sync::OnceCell;
+use zbus::{Connection, proxy, zvariant::Value};
+
+use crate::app::APPID;
+
+const APP_NAME: &str = "Clipboard Manager";
+
+/// Let the notification server choose.
+const DEFAULT_TIMEOUT: i32 = -1;
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Return the id of the notification.
+pub async fn notify(connection: &Connection, summary: &str, body: &str) -> Result<u32> {
+    let proxy = NotificationsProxy::new(connection).await?;
+
+    let id = proxy
+        .notify(
+            APP_NAME,
+            0,
+            constcat::concat!(APPID, "-symbolic"),
+            summary,
+            body,
+            &[],
+            HashMap::new(),
+            DEFAULT_TIMEOUT,
+        )
+        .await?;
+
+    Ok(id)
+}
+
+/// Shared by the notifications, connected on the first one.
+static SESSION: OnceCell<Connection> = OnceCell::const_new();
+
+/// Notify on the session bus, the errors are only logged.
+pub async fn send(summary: String, body: String) {
+    async fn send(summary: &str, body: &str) -> Result<u32> {
+        let connection = SESSION.get_or_try_init(Connection::session).await?;
+        notify(connection, summary, body).await
+    }
+
+    if let Err(e) = send(&summary, &body).await {
+        error!("can't send the notification: {e}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::UnixStream;
+    use zbus::{Guid, connection, interface, zvariant::OwnedValue};
+
+    use super::*;
+
+    /// Record the notifications, like a notification server.
+    #[derive(Default, Clone)]
+    struct MockDaemon {
+        notifications: Arc<Mutex<Vec<(String, String, String)>>>,
+    }
+
+    #[interface(name = "org.freedesktop.Notifications")]
+    impl MockDaemon {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            app_name: String,
+            _replaces_id: u32,
+            _app_icon: String,
+            summary: String,
+            body: String,
+            _actions: Vec<String>,
+            _hints: HashMap<String, OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let mut notifications = self.notifications.lock().unwrap();
+            notifications.push((app_name, summary, body));
+            notifications.len() as u32
+        }
+    }
+
+    #[tokio::test]
+    async fn mock_daemon() -> Result<()> {
+        let daemon = MockDaemon::default();
+        let (server, client) = UnixStream::pair()?;
+
+        let server = connection::Builder::unix_stream(server)
+            .server(Guid::generate())?
+            .p2p()
+            .serve_at("/org/freedesktop/Notifications", daemon.clone())?
+            .build();
+        let client = connection::Builder::unix_stream(client).p2p().build();
+
+        let (_server, client) = futures::try_join!(server, client)?;
+
+        assert_eq!(notify(&client, "Copied", "hello").await?, 1);
+        assert_eq!(notify(&client, "Copied", "world").await?, 2);
+
+        assert_eq!(
+            *daemon.notifications.lock().unwrap(),
+            vec![
+                (APP_NAME.into(), "Copied".into(), "hello".into()),
+                (APP_NAME.into(), "Copied".into(), "world".into()),
+            ]
+        );
+
+        Ok(())
+    }
+}
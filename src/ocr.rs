@@ -0,0 +1,105 @@
+//! Text recognition in images, with a locally installed tesseract.
+
+use std::{
+    io::{Read, Write},
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+
+const TESSERACT: &str = "tesseract";
+
+/// A bigger image is not worth holding a blocking worker longer.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Blocking, run it outside of the UI thread.
+pub fn recognize_text(image: &[u8]) -> Result<String> {
+    let child = spawn(Command::new(TESSERACT).args(["stdin", "stdout"]))
+        .context("can't run tesseract, is it installed?")?;
+
+    let output = wait(child, image.to_vec(), TIMEOUT)?;
+
+    if !output.status.success() {
+        bail!(
+            "tesseract failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn spawn(command: &mut Command) -> std::io::Result<Child> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+}
+
+/// Write `input` to the child, and kill it after `timeout`.
+fn wait(mut child: Child, input: Vec<u8>, timeout: Duration) -> Result<Output> {
+    // in threads, a child that doesn't read nor write would block them
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    thread::spawn(move || {
+        // the child can exit without reading everything
+        let _ = stdin.write_all(&input);
+    });
+
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut content = Vec::new();
+            let _ = pipe.read_to_end(&mut content);
+            content
+        })
+    };
+    let stdout = read(Box::new(
+        child.stdout.take().expect("stdout should be piped"),
+    ));
+    let stderr = read(Box::new(
+        child.stderr.take().expect("stderr should be piped"),
+    ));
+
+    let deadline = Instant::now() + timeout;
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            bail!("the command was killed after {timeout:?}");
+        }
+
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timeout() {
+        let cat = spawn(&mut Command::new("cat")).unwrap();
+        let output = wait(cat, b"hello".to_vec(), TIMEOUT).unwrap();
+        assert_eq!(output.stdout, b"hello");
+
+        let start = Instant::now();
+        let sleep = spawn(Command::new("sleep").arg("10")).unwrap();
+        let res = wait(sleep, Vec::new(), Duration::from_millis(100));
+
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
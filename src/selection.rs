@@ -0,0 +1,116 @@
+//! Selection of several entries, with the mouse or the keyboard.
+
+use std::collections::HashSet;
+
+use crate::db::EntryId;
+
+#[derive(Debug, Default)]
+pub struct Selection {
+    ids: HashSet<EntryId>,
+    /// Position from which the selection is extended
+    anchor: Option<usize>,
+}
+
+impl Selection {
+    pub fn contains(&self, id: EntryId) -> bool {
+        self.ids.contains(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = EntryId> + '_ {
+        self.ids.iter().copied()
+    }
+
+    /// Select or unselect the entry at `pos`, from which the selection is then extended.
+    pub fn toggle(&mut self, id: EntryId, pos: Option<usize>) {
+        if !self.ids.remove(&id) {
+            self.ids.insert(id);
+        }
+        self.anchor = pos;
+    }
+
+    /// Select the entries of `list` between the anchor and `pos`.
+    /// Without anchor, the selection starts at `from`.
+    pub fn extend(&mut self, list: impl IntoIterator<Item = EntryId>, from: usize, pos: usize) {
+        let from = *self.anchor.get_or_insert(from);
+
+        self.ids = list
+            .into_iter()
+            .skip(from.min(pos))
+            .take(from.abs_diff(pos) + 1)
+            .collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.anchor = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ids(selection: &Selection) -> Vec<EntryId> {
+        let mut ids = selection.ids().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn extend() {
+        let list = [10, 11, 12, 13, 14];
+        let mut selection = Selection::default();
+
+        // shift + down, twice
+        selection.extend(list, 1, 2);
+        assert_eq!(ids(&selection), vec![11, 12]);
+        selection.extend(list, 2, 3);
+        assert_eq!(ids(&selection), vec![11, 12, 13]);
+
+        // back above the anchor
+        selection.extend(list, 3, 0);
+        assert_eq!(ids(&selection), vec![10, 11]);
+
+        // past the end of the list
+        selection.extend(list, 0, 7);
+        assert_eq!(ids(&selection), vec![11, 12, 13, 14]);
+
+        selection.clear();
+        assert!(selection.is_empty());
+        selection.extend(list, 4, 4);
+        assert_eq!(ids(&selection), vec![14]);
+    }
+
+    #[test]
+    fn toggle() {
+        let list = [10, 11, 12, 13, 14];
+        let mut selection = Selection::default();
+
+        selection.toggle(11, Some(1));
+        selection.toggle(13, Some(3));
+        assert_eq!(ids(&selection), vec![11, 13]);
+        assert!(selection.contains(13));
+
+        selection.toggle(13, Some(3));
+        assert_eq!(ids(&selection), vec![11]);
+        assert!(!selection.contains(13));
+
+        // extended from the last toggled entry
+        selection.extend(list, 0, 4);
+        assert_eq!(ids(&selection), vec![13, 14]);
+        assert_eq!(selection.len(), 2);
+
+        // an entry no longer in the list
+        selection.toggle(20, None);
+        selection.extend(list, 2, 1);
+        assert_eq!(ids(&selection), vec![11, 12]);
+    }
+}
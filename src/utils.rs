@@ -1,39 +1,159 @@
 use std::borrow::Cow;
 use std::cmp::min;
+use std::ops::Range;
+use std::time::Duration;
 
 use chrono::Utc;
 use cosmic::{Action, Task};
 
 pub fn formatted_value(value: &str, max_lines: usize, max_chars: usize) -> Cow<'_, str> {
-    let value = value.trim();
+    let (segments, truncated) = formatted_segments(value, max_lines, max_chars);
 
-    if value.lines().count() <= max_lines && value.len() <= max_chars {
-        Cow::from(value)
+    if !truncated {
+        Cow::from(&value[segments[0].clone()])
     } else {
         let mut str = String::with_capacity(min(value.len(), max_chars + 7));
 
-        let mut lines = value.lines();
+        for (i, segment) in segments.into_iter().enumerate() {
+            if i > 0 {
+                str.push('\n');
+            }
+            str.push_str(&value[segment]);
+        }
 
-        let mut lines_count = 0;
+        str.push_str("...");
 
-        while lines_count < max_lines && str.len() < max_chars {
-            let Some(line) = lines.next() else {
-                break;
-            };
+        Cow::from(str)
+    }
+}
 
-            if lines_count > 0 {
-                str.push('\n');
+/// Same as [`formatted_value`], but if the first highlighted range would be cut,
+/// the value start at its line, or a bit before it on long lines.
+///
+/// Return the highlighted ranges in the formatted value.
+pub fn formatted_value_highlighted<'a>(
+    value: &'a str,
+    max_lines: usize,
+    max_chars: usize,
+    highlights: &[Range<usize>],
+) -> (Cow<'a, str>, Vec<Range<usize>>) {
+    let (mut segments, mut truncated) = formatted_segments(value, max_lines, max_chars);
+    let mut prefix = false;
+
+    if let Some(first) = highlights.first()
+        && !segments
+            .iter()
+            .any(|s| s.start <= first.start && first.end <= s.end)
+    {
+        let line_start = value[..first.start].rfind('\n').map_or(0, |i| i + 1);
+
+        let start = if first.start - line_start > max_chars / 2 {
+            let mut i = first.start - max_chars / 2;
+            while !value.is_char_boundary(i) {
+                i -= 1;
             }
+            i
+        } else {
+            line_start
+        };
+
+        (segments, truncated) = formatted_segments(&value[start..], max_lines, max_chars);
+        for segment in &mut segments {
+            *segment = segment.start + start..segment.end + start;
+        }
+        prefix = true;
+    }
 
-            str.push_str(split_at(line.trim(), max_chars - str.len()));
+    if !truncated && !prefix {
+        let segment = segments[0].clone();
+        let highlights = highlights
+            .iter()
+            .filter_map(|h| intersect(h, &segment, segment.start))
+            .collect();
 
-            lines_count += 1;
+        return (Cow::from(&value[segment]), highlights);
+    }
+
+    let mut str = String::with_capacity(min(value.len(), max_chars + 10));
+    let mut res_highlights = Vec::new();
+
+    if prefix {
+        str.push_str("...");
+    }
+
+    for (i, segment) in segments.into_iter().enumerate() {
+        if i > 0 {
+            str.push('\n');
         }
 
+        let pos = str.len();
+        res_highlights.extend(
+            highlights
+                .iter()
+                .filter_map(|h| intersect(h, &segment, segment.start))
+                .map(|h| h.start + pos..h.end + pos),
+        );
+
+        str.push_str(&value[segment]);
+    }
+
+    if truncated {
         str.push_str("...");
+    }
 
-        Cow::from(str)
+    (Cow::from(str), res_highlights)
+}
+
+/// Intersection of `range` and `segment`, relative to `origin`.
+fn intersect(range: &Range<usize>, segment: &Range<usize>, origin: usize) -> Option<Range<usize>> {
+    let start = range.start.max(segment.start);
+    let end = range.end.min(segment.end);
+
+    (start < end).then(|| start - origin..end - origin)
+}
+
+/// Byte ranges of `value` displayed by [`formatted_value`], joined by new lines.
+/// The bool is true if the value was truncated.
+#[allow(clippy::single_range_in_vec_init)]
+fn formatted_segments(
+    value: &str,
+    max_lines: usize,
+    max_chars: usize,
+) -> (Vec<Range<usize>>, bool) {
+    let offset = value.len() - value.trim_start().len();
+    let value = value.trim();
+
+    if value.lines().count() <= max_lines && value.len() <= max_chars {
+        return (vec![offset..offset + value.len()], false);
     }
+
+    let mut segments = Vec::new();
+    let mut len = 0;
+    let mut line_start = offset;
+
+    // the lines of `str::lines`, with their length including the line ending
+    for (line, line_len) in value
+        .split_inclusive('\n')
+        .map(|line| (line.trim_end_matches(['\n', '\r']), line.len()))
+    {
+        if segments.len() >= max_lines || len >= max_chars {
+            break;
+        }
+
+        if !segments.is_empty() {
+            len += 1;
+        }
+
+        let line_offset = line_start + (line.len() - line.trim_start().len());
+        let kept = split_at(line.trim(), max_chars - len);
+
+        segments.push(line_offset..line_offset + kept.len());
+        len += kept.len();
+
+        line_start += line_len;
+    }
+
+    (segments, true)
 }
 
 fn split_at(str: &str, n: usize) -> &str {
@@ -57,3 +177,134 @@ pub fn task_message<M: Send + 'static>(message: M) -> Task<Action<M>> {
 pub fn now_millis() -> i64 {
     Utc::now().timestamp_millis()
 }
+
+/// Replace the `\n`, `\t` and `\\` escapes, to type them in a single line.
+pub fn unescape(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('\\') => res.push('\\'),
+            Some(c) => {
+                res.push('\\');
+                res.push(c);
+            }
+            None => res.push('\\'),
+        }
+    }
+
+    res
+}
+
+/// Parse a duration like `30s`, `15m`, `1h`, `2d` or `1w`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let count: u64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(count.checked_mul(seconds)?))
+}
+
+/// Format a remaining time as `m:ss`, or `h:mm:ss` above an hour.
+pub fn format_remaining(remaining: Duration) -> String {
+    // round up, to not show 0:00 during the last second
+    let seconds = remaining.as_millis().div_ceil(1000);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formatted() {
+        assert_eq!(formatted_value("  hello  ", 5, 200), "hello");
+        assert_eq!(formatted_value("a\n  b  \nc", 2, 200), "a\nb...");
+        assert_eq!(formatted_value("abcdef", 5, 3), "abc...");
+        assert_eq!(formatted_value("a\r\n  b  \r\nc", 2, 200), "a\nb...");
+    }
+
+    #[test]
+    fn formatted_highlighted() {
+        let (value, highlights) = formatted_value_highlighted("  hello world", 5, 200, &[8..13]);
+        assert_eq!(value, "hello world");
+        assert_eq!(highlights, vec![6..11]);
+
+        // the match is on a hidden line
+        let (value, highlights) =
+            formatted_value_highlighted("a\nb\n  c match\nd\ne", 2, 200, &[8..13]);
+        assert_eq!(value, "...c match\nd...");
+        assert_eq!(highlights, vec![5..10]);
+
+        // with CRLF line endings
+        let (value, highlights) =
+            formatted_value_highlighted("a\r\nb\r\n  c match\r\nd\r\ne", 2, 200, &[10..15]);
+        assert_eq!(value, "...c match\nd...");
+        assert_eq!(highlights, vec![5..10]);
+
+        // the match is far in a long line
+        let text = format!("{}match{}", "x".repeat(300), "y".repeat(300));
+        let (value, highlights) = formatted_value_highlighted(&text, 5, 20, &[300..305]);
+        assert_eq!(
+            value,
+            format!("...{}match{}...", "x".repeat(10), "y".repeat(5))
+        );
+        assert_eq!(highlights, vec![13..18]);
+    }
+
+    #[test]
+    fn unescaped() {
+        assert_eq!(unescape("\\n"), "\n");
+        assert_eq!(unescape(", \\t|"), ", \t|");
+        assert_eq!(unescape("a\\\\nb"), "a\\nb");
+        assert_eq!(unescape("\\x\\"), "\\x\\");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("15m"), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(
+            parse_duration("2w"),
+            Some(Duration::from_secs(14 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_duration("15"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("1y"), None);
+    }
+
+    #[test]
+    fn remaining() {
+        assert_eq!(format_remaining(Duration::from_secs(5 * 60)), "5:00");
+        assert_eq!(format_remaining(Duration::from_millis(61_500)), "1:02");
+        assert_eq!(format_remaining(Duration::from_millis(200)), "0:01");
+        assert_eq!(format_remaining(Duration::ZERO), "0:00");
+        assert_eq!(
+            format_remaining(Duration::from_secs(60 * 60 + 5)),
+            "1:00:05"
+        );
+    }
+}
@@ -1,11 +1,13 @@
-use std::{borrow::Cow, cmp::min, sync::LazyLock};
+use std::{borrow::Cow, cmp::min, ops::Range, sync::LazyLock, time::Duration};
 
 use cosmic::{
     Apply, Element,
     iced::{Alignment, Length, alignment::Horizontal, padding},
     iced_widget::{
-        Stack,
+        Stack, rich_text,
         scrollable::{Direction, Scrollbar},
+        span,
+        text::Span,
     },
     theme::Button,
     widget::{
@@ -17,12 +19,13 @@ use cosmic::{
 use itertools::Itertools;
 
 use crate::{
-    app::{AppState, ClipboardState, ErrorState},
-    db::{Content, DbTrait, EntryTrait},
+    app::AppState,
+    clipboard::{ClipboardState, ErrorState},
+    db::{ClearRange, Content, DbTrait, EntryTrait},
     fl, icon, icon_button,
-    message::{AppMsg, ConfigMsg, ContextMenuMsg},
+    message::{AppMsg, ConfigMsg, ContextMenuMsg, SelectionMsg},
     my_widget,
-    utils::formatted_value,
+    utils::{format_remaining, formatted_value, formatted_value_highlighted},
 };
 
 pub static SCROLLABLE_ID: LazyLock<Id> = LazyLock::new(|| Id::new("scrollable"));
@@ -41,6 +44,40 @@ impl<Db: DbTrait> AppState<Db> {
                 .into()
         }
 
+        fn clear_range<'a>(label: String, range: ClearRange) -> Element<'a, AppMsg> {
+            button::standard(label)
+                .on_press(AppMsg::ClearRange(range))
+                .into()
+        }
+
+        const MINUTE: Duration = Duration::from_secs(60);
+        const HOUR: Duration = Duration::from_secs(60 * 60);
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+        let pause: Element<_> = if self.pause.is_some() {
+            let label = match self.pause_remaining() {
+                Some(remaining) => fl!("paused_for", remaining = format_remaining(remaining)),
+                None => fl!("paused_until_logout"),
+            };
+
+            row()
+                .push(text(label))
+                .push(space::horizontal())
+                .push(button::standard(fl!("resume")).on_press(AppMsg::Resume))
+                .align_y(Alignment::Center)
+                .into()
+        } else {
+            row()
+                .spacing(5)
+                .push(
+                    button::standard(fl!("pause_5_minutes"))
+                        .on_press(AppMsg::Pause(Some(5 * MINUTE))),
+                )
+                .push(button::standard(fl!("pause_1_hour")).on_press(AppMsg::Pause(Some(HOUR))))
+                .push(button::standard(fl!("pause_until_logout")).on_press(AppMsg::Pause(None)))
+                .into()
+        };
+
         column()
             .width(Length::Fill)
             .spacing(20)
@@ -50,6 +87,7 @@ impl<Db: DbTrait> AppState<Db> {
                 self.config.private_mode,
                 |v| AppMsg::Config(ConfigMsg::PrivateMode(v)),
             ))
+            .push(pause)
             // .push(toggle_settings(
             //     fl!("horizontal_layout"),
             //     self.config.horizontal,
@@ -60,17 +98,108 @@ impl<Db: DbTrait> AppState<Db> {
                 self.config.unique_session,
                 |v| AppMsg::Config(ConfigMsg::UniqueSession(v)),
             ))
+            .push(toggle_settings(
+                fl!("fuzzy_search"),
+                self.config.fuzzy_search,
+                |v| AppMsg::Config(ConfigMsg::FuzzySearch(v)),
+            ))
+            .push(toggle_settings(
+                fl!("sort_by_usage"),
+                self.config.sort_by_usage,
+                |v| AppMsg::Config(ConfigMsg::SortByUsage(v)),
+            ))
+            .push(toggle_settings(
+                fl!("keep_copied_position"),
+                self.config.keep_copied_position,
+                |v| AppMsg::Config(ConfigMsg::KeepCopiedPosition(v)),
+            ))
+            .push(toggle_settings(
+                fl!("text_recognition"),
+                self.config.text_recognition,
+                |v| AppMsg::Config(ConfigMsg::TextRecognition(v)),
+            ))
+            .push(toggle_settings(
+                fl!("keep_clipboard_alive"),
+                self.config.keep_clipboard_alive,
+                |v| AppMsg::Config(ConfigMsg::KeepClipboardAlive(v)),
+            ))
+            .push(toggle_settings(
+                fl!("new_entries_badge"),
+                self.config.new_entries_badge,
+                |v| AppMsg::Config(ConfigMsg::NewEntriesBadge(v)),
+            ))
+            .push(toggle_settings(
+                fl!("notify_errors"),
+                self.config.notify_errors,
+                |v| AppMsg::Config(ConfigMsg::NotifyErrors(v)),
+            ))
+            .push(toggle_settings(
+                fl!("notify_filtered"),
+                self.config.notify_filtered,
+                |v| AppMsg::Config(ConfigMsg::NotifyFiltered(v)),
+            ))
+            .push(toggle_settings(
+                fl!("notify_copied"),
+                self.config.notify_copied,
+                |v| AppMsg::Config(ConfigMsg::NotifyCopied(v)),
+            ))
+            .push(toggle_settings(
+                fl!("duplicates_ignore_whitespace"),
+                self.config.duplicates_ignore_whitespace,
+                |v| AppMsg::Config(ConfigMsg::DuplicatesIgnoreWhitespace(v)),
+            ))
+            .push(toggle_settings(
+                fl!("duplicates_ignore_case"),
+                self.config.duplicates_ignore_case,
+                |v| AppMsg::Config(ConfigMsg::DuplicatesIgnoreCase(v)),
+            ))
+            .push(
+                row()
+                    .push(text(fl!("concatenation_separator")))
+                    .push(space::horizontal())
+                    .push(
+                        text_input::text_input("", &self.config.concatenation_separator)
+                            .on_input(|v| AppMsg::Config(ConfigMsg::ConcatenationSeparator(v)))
+                            .width(Length::Fixed(80f32)),
+                    )
+                    .align_y(Alignment::Center),
+            )
+            .push(button::standard(fl!("merge_duplicates")).on_press(AppMsg::MergeDuplicates))
+            .push(
+                row()
+                    .spacing(5)
+                    .push(clear_range(
+                        fl!("clear_last_15_minutes"),
+                        ClearRange::Last(15 * MINUTE),
+                    ))
+                    .push(clear_range(fl!("clear_last_hour"), ClearRange::Last(HOUR))),
+            )
+            .push(
+                row()
+                    .spacing(5)
+                    .push(clear_range(fl!("clear_last_day"), ClearRange::Last(DAY)))
+                    .push(clear_range(
+                        fl!("clear_older_than_week"),
+                        ClearRange::OlderThan(7 * DAY),
+                    )),
+            )
             .push(button::destructive(fl!("clear_entries")).on_press(AppMsg::Clear))
             .into()
     }
 
     pub fn popup_view(&self) -> Element<'_, AppMsg> {
-        container(if let ClipboardState::Error(e) = &self.clipboard_state {
+        container(if let ClipboardState::Error(e) = &self.clipboard.state {
             self.error_view(e)
         } else if let Some(qr_code_res) = &self.qr_code {
             self.qr_code_view(qr_code_res)
-        } else {
+        } else if self.selected.is_empty() {
             self.list_view()
+        } else {
+            column()
+                .spacing(20)
+                .push(self.selection_view())
+                .push(self.list_view())
+                .into()
         })
         .height(if self.config.horizontal {
             Length::Fill
@@ -89,6 +218,42 @@ impl<Db: DbTrait> AppState<Db> {
         self.db.len() / self.config.maximum_entries_by_page.get() as usize
     }
 
+    fn selection_view(&self) -> Element<'_, AppMsg> {
+        let action = |label: String, message: SelectionMsg| {
+            button::text(label).on_press(AppMsg::Selection(message))
+        };
+
+        column()
+            .spacing(5)
+            .push(
+                row()
+                    .push(text(fl!("selected_entries", count = self.selected.len())))
+                    .push(space::horizontal())
+                    .push(action(fl!("cancel"), SelectionMsg::Clear))
+                    .align_y(Alignment::Center),
+            )
+            .push(
+                row()
+                    .spacing(5)
+                    .push(action(
+                        fl!("copy_concatenated"),
+                        SelectionMsg::CopyConcatenated,
+                    ))
+                    .push(action(fl!("export"), SelectionMsg::Export)),
+            )
+            .push(
+                row()
+                    .spacing(5)
+                    .push(action(fl!("add_favorite"), SelectionMsg::AddFavorite))
+                    .push(action(fl!("remove_favorite"), SelectionMsg::RemoveFavorite))
+                    .push(
+                        action(fl!("delete_entry"), SelectionMsg::Delete)
+                            .class(Button::Destructive),
+                    ),
+            )
+            .into()
+    }
+
     fn list_view(&self) -> Element<'_, AppMsg> {
         column()
             .spacing(20)
@@ -134,9 +299,9 @@ impl<Db: DbTrait> AppState<Db> {
                     .get(range)
                     .map(|(pos, data)| {
                         match data.preferred_content(&self.preferred_mime_types_regex) {
-                            Some((_, content)) => match content {
+                            Some(((mime, _), content)) => match content {
                                 Content::Text(text) => {
-                                    self.text_entry(data, pos == self.focused, text)
+                                    self.text_entry(data, pos == self.focused, mime, text)
                                 }
                                 Content::Image(image) => {
                                     self.image_entry(data, pos == self.focused, image)
@@ -208,12 +373,18 @@ impl<Db: DbTrait> AppState<Db> {
     }
 
     fn error_view(&self, error: &ErrorState) -> Element<'_, AppMsg> {
-        match error {
+        let error = match error {
             ErrorState::MissingDataControlProtocol => {
-                text("Error: The data control protocol is not active").into()
+                text("Error: The data control protocol is not active")
             }
-            ErrorState::Other(e) => text(e.to_string()).into(),
-        }
+            ErrorState::Other(e) => text(e.to_string()),
+        };
+
+        column()
+            .spacing(20)
+            .push(error)
+            .push(button::text(fl!("retry")).on_press(AppMsg::RetryConnectingClipboard))
+            .into()
     }
 
     fn image_entry<'a>(
@@ -277,14 +448,32 @@ impl<Db: DbTrait> AppState<Db> {
         &'a self,
         entry: &'a Db::Entry,
         is_focused: bool,
+        mime: &str,
         content: &'a str,
     ) -> Element<'a, AppMsg> {
         // todo: remove this max line things: display the maximum
-        if self.config.horizontal {
-            self.base_entry(entry, is_focused, text(formatted_value(content, 10, 500)))
+        let (max_lines, max_chars) = if self.config.horizontal {
+            (10, 500)
         } else {
-            self.base_entry(entry, is_focused, text(formatted_value(content, 5, 200)))
+            (5, 200)
+        };
+
+        if self.db.is_search_active()
+            && let Some(search_match) = self.db.get_search_match(entry.id())
+            && search_match.mime == mime
+            && !search_match.ranges.is_empty()
+        {
+            let (content, ranges) =
+                formatted_value_highlighted(content, max_lines, max_chars, &search_match.ranges);
+
+            return self.base_entry(entry, is_focused, highlighted_text(&content, &ranges));
         }
+
+        self.base_entry(
+            entry,
+            is_focused,
+            text(formatted_value(content, max_lines, max_chars)),
+        )
     }
 
     fn base_entry<'a>(
@@ -293,14 +482,18 @@ impl<Db: DbTrait> AppState<Db> {
         is_focused: bool,
         content: impl Into<Element<'a, AppMsg>>,
     ) -> Element<'a, AppMsg> {
+        let is_selected = self.selected.contains(entry.id());
+
         let btn = button::custom(content)
-            .on_press(AppMsg::Copy(entry.id()))
+            .on_press(AppMsg::EntryPressed(entry.id()))
             .class(Button::Custom {
                 active: Box::new(move |focused, theme| {
                     let rad_s = theme.cosmic().corner_radii.radius_s;
                     let focused = is_focused || focused;
 
-                    let a = if focused {
+                    let a = if is_selected {
+                        button::Catalog::active(theme, focused, focused, &Button::Suggested)
+                    } else if focused {
                         button::Catalog::hovered(theme, focused, focused, &Button::Text)
                     } else {
                         button::Catalog::active(theme, focused, focused, &Button::Standard)
@@ -358,13 +551,28 @@ impl<Db: DbTrait> AppState<Db> {
             btn
         };
 
-        let overlay: Element<_> = column()
-            .push(if entry.is_favorite() {
-                button::text(fl!("remove_favorite"))
-                    .on_press(ContextMenuMsg::RemoveFavorite(entry.id()))
-            } else {
-                button::text(fl!("add_favorite")).on_press(ContextMenuMsg::AddFavorite(entry.id()))
-            })
+        let mut overlay = column().push(if entry.is_favorite() {
+            button::text(fl!("remove_favorite"))
+                .on_press(ContextMenuMsg::RemoveFavorite(entry.id()))
+        } else {
+            button::text(fl!("add_favorite")).on_press(ContextMenuMsg::AddFavorite(entry.id()))
+        });
+
+        if entry.recognized_text().is_some_and(|text| !text.is_empty()) {
+            overlay = overlay.push(
+                button::text(fl!("copy_recognized_text"))
+                    .on_press(ContextMenuMsg::CopyRecognizedText(entry.id())),
+            );
+        }
+
+        if self.can_set_primary() {
+            overlay = overlay.push(
+                button::text(fl!("copy_to_primary"))
+                    .on_press(ContextMenuMsg::CopyToPrimary(entry.id())),
+            );
+        }
+
+        let overlay: Element<_> = overlay
             .push(
                 button::text(fl!("show_qr_code")).on_press(ContextMenuMsg::ShowQrCode(entry.id())),
             )
@@ -384,6 +592,25 @@ impl<Db: DbTrait> AppState<Db> {
     }
 }
 
+fn highlighted_text<'a>(content: &str, ranges: &[Range<usize>]) -> Element<'a, AppMsg> {
+    let mut spans: Vec<Span<'a>> = Vec::with_capacity(ranges.len() * 2 + 1);
+    let mut last = 0;
+
+    for range in ranges {
+        if range.start > last {
+            spans.push(span(content[last..range.start].to_string()));
+        }
+        spans.push(span(content[range.clone()].to_string()).font(cosmic::font::bold()));
+        last = range.end;
+    }
+
+    if last < content.len() {
+        spans.push(span(content[last..].to_string()));
+    }
+
+    rich_text(spans).into()
+}
+
 /*
 let items = vec![
             if entry.is_favorite() {
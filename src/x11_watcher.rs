@@ -0,0 +1,615 @@
+//! Clipboard of X11 sessions, watched with the XFixes extension.
+
+use std::{
+    collections::HashMap,
+    io::PipeReader,
+    thread,
+    time::{Duration, Instant},
+};
+
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    errors::ReplyOrIdError,
+    protocol::{
+        Event,
+        xfixes::{self, ConnectionExt as _, SelectionEventMask},
+        xproto::{
+            Atom, AtomEnum, ConnectionExt as _, CreateWindowAux, EventMask, Property, Timestamp,
+            Window, WindowClass,
+        },
+    },
+    rust_connection::RustConnection,
+};
+
+use crate::{
+    clipboard::{ClipboardBackend, ReadPolicy, SelectionHandle, pipe_from},
+    clipboard_watcher::Error,
+    db::MimeDataMap,
+};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        CLIPBOARD,
+        TARGETS,
+        INCR,
+        // where the owner writes the converted selection
+        CLIPBOARD_MANAGER_TRANSFER,
+    }
+}
+
+/// Targets which are not mime types, but still represent the content.
+const TEXT_TARGETS: &[&str] = &["UTF8_STRING", "STRING", "TEXT"];
+
+/// Between the checks of the transfer events, to stop at the timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn is_content_target(name: &str) -> bool {
+    name.contains('/') || TEXT_TARGETS.contains(&name)
+}
+
+fn x11_error(e: impl Into<ReplyOrIdError>) -> Error {
+    Error::X11Communication(e.into())
+}
+
+pub struct Watcher {
+    conn: RustConnection,
+    /// Never mapped, receives the converted selections.
+    window: Window,
+    atoms: Atoms,
+    /// Owner change received while reading the previous selection.
+    pending: Option<xfixes::SelectionNotifyEvent>,
+}
+
+impl Watcher {
+    pub fn init() -> Result<Self, Error> {
+        let (conn, screen_num) = x11rb::connect(None).map_err(Error::X11Connection)?;
+
+        Self::with_connection(conn, screen_num)
+    }
+
+    pub fn with_connection(conn: RustConnection, screen_num: usize) -> Result<Self, Error> {
+        if conn
+            .extension_information(xfixes::X11_EXTENSION_NAME)
+            .map_err(x11_error)?
+            .is_none()
+        {
+            return Err(Error::MissingProtocol {
+                name: xfixes::X11_EXTENSION_NAME,
+                version: 1,
+            });
+        }
+
+        conn.xfixes_query_version(1, 0)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        let atoms = Atoms::new(&conn)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        let screen = &conn.setup().roots[screen_num];
+        let window = conn.generate_id().map_err(x11_error)?;
+
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )
+        .map_err(x11_error)?;
+
+        conn.xfixes_select_selection_input(
+            window,
+            atoms.CLIPBOARD,
+            SelectionEventMask::SET_SELECTION_OWNER
+                | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+        )
+        .map_err(x11_error)?;
+
+        conn.flush().map_err(x11_error)?;
+
+        Ok(Watcher {
+            conn,
+            window,
+            atoms,
+            pending: None,
+        })
+    }
+
+    fn next_owner_change(&mut self) -> Result<xfixes::SelectionNotifyEvent, Error> {
+        if let Some(event) = self.pending.take() {
+            return Ok(event);
+        }
+
+        loop {
+            if let Event::XfixesSelectionNotify(event) =
+                self.conn.wait_for_event().map_err(x11_error)?
+            {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Wait for an event of the transfer until `deadline`, keeping the owner changes for later.
+    fn next_transfer_event(&mut self, deadline: Instant) -> Result<Option<Event>, Error> {
+        loop {
+            match self.conn.poll_for_event().map_err(x11_error)? {
+                Some(Event::XfixesSelectionNotify(event)) => self.pending = Some(event),
+                Some(event) => return Ok(Some(event)),
+                None if Instant::now() >= deadline => return Ok(None),
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Ask the owner to convert the selection to `target`, then read the result.
+    /// Return [`None`] when the owner refused, was slower than the policy timeout,
+    /// or sent more than its max bytes.
+    fn convert(
+        &mut self,
+        target: Atom,
+        time: Timestamp,
+        policy: &ReadPolicy,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let property = self.atoms.CLIPBOARD_MANAGER_TRANSFER;
+        let deadline = Instant::now() + policy.timeout;
+        let too_big = |len: usize| policy.max_bytes.is_some_and(|max| len as u64 > max);
+
+        self.conn
+            .convert_selection(self.window, self.atoms.CLIPBOARD, target, property, time)
+            .map_err(x11_error)?;
+        self.conn.flush().map_err(x11_error)?;
+
+        loop {
+            match self.next_transfer_event(deadline)? {
+                Some(Event::SelectionNotify(event))
+                    if event.requestor == self.window && event.target == target =>
+                {
+                    if event.property == u32::from(AtomEnum::NONE) {
+                        return Ok(None);
+                    }
+                    break;
+                }
+                Some(_) => (),
+                None => {
+                    warn!("timeout on the X11 selection");
+                    return Ok(None);
+                }
+            }
+        }
+
+        let reply = self
+            .conn
+            .get_property(true, self.window, property, AtomEnum::ANY, 0, u32::MAX)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        if reply.type_ != self.atoms.INCR {
+            if too_big(reply.value.len()) {
+                debug!("X11 selection too big");
+                return Ok(None);
+            }
+            return Ok(Some(reply.value));
+        }
+
+        // the owner writes the next chunk each time we delete the property,
+        // until an empty one
+        let mut content = Vec::new();
+
+        loop {
+            match self.next_transfer_event(deadline)? {
+                Some(Event::PropertyNotify(event))
+                    if event.window == self.window
+                        && event.atom == property
+                        && event.state == Property::NEW_VALUE =>
+                {
+                    let chunk = self
+                        .conn
+                        .get_property(true, self.window, property, AtomEnum::ANY, 0, u32::MAX)
+                        .map_err(x11_error)?
+                        .reply()
+                        .map_err(x11_error)?
+                        .value;
+
+                    if chunk.is_empty() {
+                        return Ok(Some(content));
+                    }
+
+                    content.extend(chunk);
+
+                    // the owner gives up once we stop deleting the property
+                    if too_big(content.len()) {
+                        debug!("X11 selection too big");
+                        return Ok(None);
+                    }
+                }
+                Some(_) => (),
+                None => {
+                    warn!("timeout on the X11 selection");
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn read_selection(
+        &mut self,
+        time: Timestamp,
+        policy: &ReadPolicy,
+    ) -> Result<MimeDataMap, Error> {
+        let Some(targets) = self.convert(self.atoms.TARGETS, time, policy)? else {
+            return Ok(MimeDataMap::new());
+        };
+
+        let targets = targets
+            .chunks_exact(4)
+            .map(|atom| Atom::from_ne_bytes(atom.try_into().unwrap()))
+            .collect::<Vec<_>>();
+
+        let mut names = HashMap::new();
+
+        for target in targets {
+            let name = self
+                .conn
+                .get_atom_name(target)
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?
+                .name;
+
+            let name = String::from_utf8_lossy(&name).into_owned();
+
+            if is_content_target(&name) {
+                names.insert(name, target);
+            }
+        }
+
+        let mut data = MimeDataMap::new();
+
+        for name in policy.select(names.keys().cloned())? {
+            match self.convert(names[&name], time, policy)? {
+                Some(content) => {
+                    data.insert(name, content);
+                }
+                None => debug!("conversion failed: {name}"),
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+impl ClipboardBackend for Watcher {
+    fn watch(&mut self, policy: &ReadPolicy) -> Result<Vec<(String, PipeReader)>, Error> {
+        let event = self.next_owner_change()?;
+
+        if event.owner == x11rb::NONE {
+            info!("keyboard is empty");
+            return Err(Error::ClipboardEmpty);
+        }
+
+        self.read_selection(event.timestamp, policy)?
+            .into_iter()
+            .map(|(mime_type, content)| {
+                let pipe = pipe_from(content).map_err(Error::PipeCreation)?;
+                Ok((mime_type, pipe))
+            })
+            .collect()
+    }
+
+    fn selection(&self) -> Option<SelectionHandle> {
+        // owning the selection would need to answer the conversions
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        process::{Child, Command},
+        thread,
+        time::Duration,
+    };
+
+    use x11rb::{
+        protocol::xproto::{
+            ChangeWindowAttributesAux, PropMode, SELECTION_NOTIFY_EVENT, SelectionNotifyEvent,
+        },
+        wrapper::ConnectionExt as _,
+    };
+
+    use crate::clipboard::read_offer;
+
+    use super::*;
+
+    const DISPLAY: &str = ":97";
+
+    struct Xvfb(Child);
+
+    impl Xvfb {
+        fn start() -> Self {
+            let xvfb = Xvfb(
+                Command::new("Xvfb")
+                    .args([DISPLAY, "-nolisten", "tcp"])
+                    .spawn()
+                    .expect("Xvfb is not installed"),
+            );
+
+            for _ in 0..50 {
+                if x11rb::connect(Some(DISPLAY)).is_ok() {
+                    return xvfb;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+
+            panic!("Xvfb didn't start");
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// How the owner answers the conversion to a target.
+    enum Transfer {
+        Direct(&'static [u8]),
+        /// With the INCR protocol, in chunks of this size.
+        Incr(&'static [u8], usize),
+        /// Never answered.
+        Stalled,
+    }
+
+    /// Own the clipboard in another client, answering `requests` conversions,
+    /// then exit.
+    fn copy(content: &'static [(&'static str, Transfer)], requests: usize) {
+        let (conn, screen_num) = x11rb::connect(Some(DISPLAY)).unwrap();
+        let screen = &conn.setup().roots[screen_num];
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+
+        let atoms = Atoms::new(&conn).unwrap().reply().unwrap();
+        let targets = content
+            .iter()
+            .map(|(name, _)| conn.intern_atom(false, name.as_bytes()).unwrap())
+            .map(|cookie| cookie.reply().unwrap().atom)
+            .collect::<Vec<_>>();
+
+        conn.set_selection_owner(window, atoms.CLIPBOARD, x11rb::CURRENT_TIME)
+            .unwrap();
+        conn.flush().unwrap();
+
+        thread::spawn(move || {
+            // received while sending the chunks of a transfer
+            let mut next = None;
+
+            for _ in 0..requests {
+                let request = loop {
+                    if let Some(request) = next.take() {
+                        break request;
+                    }
+                    match conn.wait_for_event() {
+                        Ok(Event::SelectionRequest(request)) => break request,
+                        Ok(_) => (),
+                        Err(_) => return,
+                    }
+                };
+
+                let mut property = request.property;
+                let mut chunks = None;
+
+                if request.target == atoms.TARGETS {
+                    let mut atoms = vec![atoms.TARGETS];
+                    atoms.extend(&targets);
+                    conn.change_property32(
+                        PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        AtomEnum::ATOM,
+                        &atoms,
+                    )
+                    .unwrap();
+                } else if let Some(i) = targets.iter().position(|t| *t == request.target) {
+                    match content[i].1 {
+                        Transfer::Direct(data) => {
+                            conn.change_property8(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                request.target,
+                                data,
+                            )
+                            .unwrap();
+                        }
+                        Transfer::Incr(data, size) => {
+                            // to know when the requestor deleted the property
+                            conn.change_window_attributes(
+                                request.requestor,
+                                &ChangeWindowAttributesAux::new()
+                                    .event_mask(EventMask::PROPERTY_CHANGE),
+                            )
+                            .unwrap();
+                            conn.change_property32(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                atoms.INCR,
+                                &[data.len() as u32],
+                            )
+                            .unwrap();
+                            chunks = Some(data.chunks(size).chain([&[][..]]));
+                        }
+                        Transfer::Stalled => continue,
+                    }
+                } else {
+                    property = AtomEnum::NONE.into();
+                }
+
+                let notify = SelectionNotifyEvent {
+                    response_type: SELECTION_NOTIFY_EVENT,
+                    sequence: 0,
+                    time: request.time,
+                    requestor: request.requestor,
+                    selection: request.selection,
+                    target: request.target,
+                    property,
+                };
+                conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify)
+                    .unwrap();
+                conn.flush().unwrap();
+
+                // each chunk after the requestor deleted the previous one
+                'chunks: for chunk in chunks.into_iter().flatten() {
+                    loop {
+                        match conn.wait_for_event() {
+                            Ok(Event::PropertyNotify(event))
+                                if event.atom == property && event.state == Property::DELETE =>
+                            {
+                                break;
+                            }
+                            // the requestor gave up this transfer
+                            Ok(Event::SelectionRequest(request)) => {
+                                next = Some(request);
+                                break 'chunks;
+                            }
+                            Ok(_) => (),
+                            Err(_) => return,
+                        }
+                    }
+
+                    conn.change_property8(
+                        PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        request.target,
+                        chunk,
+                    )
+                    .unwrap();
+                    conn.flush().unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn content_targets() {
+        for target in ["UTF8_STRING", "STRING", "TEXT", "text/plain", "image/png"] {
+            assert!(is_content_target(target), "{target}");
+        }
+        for target in ["TARGETS", "MULTIPLE", "TIMESTAMP", "SAVE_TARGETS"] {
+            assert!(!is_content_target(target), "{target}");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Xvfb"]
+    async fn watch_xvfb() {
+        let _xvfb = Xvfb::start();
+
+        let (conn, screen_num) = x11rb::connect(Some(DISPLAY)).unwrap();
+        let mut watcher = Watcher::with_connection(conn, screen_num).unwrap();
+
+        // TARGETS, then the 2 targets
+        copy(
+            &[
+                ("UTF8_STRING", Transfer::Direct(b"hello")),
+                ("text/html", Transfer::Direct(b"<b>hello</b>")),
+            ],
+            3,
+        );
+
+        let policy = ReadPolicy::default();
+        let data = read_offer(watcher.watch(&policy).unwrap(), &policy)
+            .await
+            .unwrap();
+        assert_eq!(
+            data,
+            MimeDataMap::from([
+                ("UTF8_STRING".into(), b"hello".to_vec()),
+                ("text/html".into(), b"<b>hello</b>".to_vec()),
+            ])
+        );
+
+        // the owner exited
+        assert!(matches!(watcher.watch(&policy), Err(Error::ClipboardEmpty)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Xvfb"]
+    async fn limits_xvfb() {
+        let _xvfb = Xvfb::start();
+
+        let (conn, screen_num) = x11rb::connect(Some(DISPLAY)).unwrap();
+        let mut watcher = Watcher::with_connection(conn, screen_num).unwrap();
+
+        let policy = ReadPolicy {
+            timeout: Duration::from_millis(300),
+            max_bytes: Some(1000),
+            ..ReadPolicy::default()
+        };
+
+        copy(
+            &[
+                ("text/plain", Transfer::Direct(b"hello")),
+                ("text/html", Transfer::Stalled),
+            ],
+            3,
+        );
+
+        let start = Instant::now();
+        let data = read_offer(watcher.watch(&policy).unwrap(), &policy)
+            .await
+            .unwrap();
+        assert_eq!(
+            data,
+            MimeDataMap::from([("text/plain".into(), b"hello".to_vec())])
+        );
+        assert!(start.elapsed() >= policy.timeout);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert!(matches!(watcher.watch(&policy), Err(Error::ClipboardEmpty)));
+
+        // TARGETS, then each one
+        copy(
+            &[
+                ("text/plain", Transfer::Incr(&[b'a'; 600], 200)),
+                ("image/png", Transfer::Incr(&[0; 3000], 500)),
+            ],
+            3,
+        );
+
+        let data = read_offer(watcher.watch(&policy).unwrap(), &policy)
+            .await
+            .unwrap();
+        assert_eq!(
+            data,
+            MimeDataMap::from([("text/plain".into(), vec![b'a'; 600])])
+        );
+    }
+}